@@ -2,40 +2,136 @@
 // SPDX-License-Identifier: MIT
 
 use oxidot::{
-    cluster::BranchTarget,
-    config::{ClusterDefinition, WorkTreeAlias},
-    path::{default_cluster_store_dir, home_dir},
-    store::Store,
+    cluster::{
+        crypt::CryptFilter, profile::ProfileSelection, template::TemplateFilter, BranchTarget,
+    },
+    config::{
+        edit::{
+            annotate_problem, normalize_key, strip_problem, DefinitionEditor, Error as EditError,
+        },
+        lint::{PLACEHOLDER_DESCRIPTION, PLACEHOLDER_URL},
+        migrate::{migrate, SCHEMA_VERSION},
+        schema::cluster_definition_schema,
+        ClusterDefinition, ClusterDependency, ClusterRemote, OutputFormat, UserConfig,
+        WorkTreeAlias,
+    },
+    doctor::{self, Severity},
+    path::{cluster_store_dir, home_dir, user_config_file},
+    store::{
+        lock::{LockMode, StoreLock},
+        Selection, Store,
+    },
 };
 
-use anyhow::Result;
-use clap::{Parser, Subcommand};
-use std::{ffi::OsString, path::PathBuf, process::exit};
-use tracing::error;
-use tracing_subscriber::{fmt, prelude::*, EnvFilter};
+use anyhow::{bail, Context, Result};
+use clap::{Parser, Subcommand, ValueEnum};
+use std::{
+    ffi::OsString,
+    fs::{create_dir_all, read_to_string, remove_file, write},
+    io::{stdin, stdout, ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+    process::exit,
+};
+use tracing::{error, info, warn};
+use tracing_subscriber::{fmt, prelude::*, EnvFilter, Layer, Registry};
 
 #[derive(Debug, Clone, Parser)]
 #[command(
     about,
-    override_usage = "\n  oxidot [options] <oxidot-command>\n  oxidot [options] [cluster]... <git-command>",
+    override_usage = "\n  oxidot [options] <oxidot-command>\n  oxidot [options] [cluster]... <git-command>\n  oxidot [options] (--all | --deployed) [--] <git-command>",
     subcommand_help_heading = "Commands",
+    arg_required_else_help = true,
     version
 )]
 struct Cli {
+    /// Path to cluster store [env: OXIDOT_STORE].
+    #[arg(long, global = true, value_name = "path")]
+    pub store: Option<PathBuf>,
+
+    /// Do not run deployment hooks of clusters.
+    #[arg(long, global = true)]
+    pub no_hooks: bool,
+
+    /// Run Git command on every cluster.
+    #[arg(short, long, group = "selection")]
+    pub all: bool,
+
+    /// Run Git command on every deployed cluster.
+    #[arg(short, long, group = "selection")]
+    pub deployed: bool,
+
+    /// Arguments after "--" that are passed to Git as is.
+    #[arg(skip)]
+    pub git_args: Vec<OsString>,
+
     #[command(subcommand)]
-    pub command: Command,
+    pub command: Option<Command>,
 }
 
 impl Cli {
-    async fn run(self) -> Result<()> {
-        match self.command {
-            Command::Init(opts) => run_init(opts),
-            Command::Clone(opts) => run_clone(opts).await,
-            Command::Deploy(opts) => run_deploy(opts),
-            Command::Undeploy(opts) => run_undeploy(opts),
-            Command::Status(opts) => run_status(opts),
-            Command::Remove(opts) => run_remove(opts),
-            Command::Git(opts) => run_git(opts),
+    /// Parse command-line arguments of current process.
+    ///
+    /// Clap cannot mix external subcommands with trailing arguments. Thus,
+    /// everything after a "--" that does not follow an Oxidot command is
+    /// treated as a Git command, e.g., `oxidot --all -- status`.
+    fn parse_with_passthrough() -> Self {
+        let args = std::env::args_os().collect::<Vec<_>>();
+        let Some(split) = args.iter().position(|arg| arg == "--") else {
+            return Self::parse_from(args);
+        };
+
+        let Ok(mut cli) = Self::try_parse_from(&args[..split]) else {
+            return Self::parse_from(args);
+        };
+        match cli.command.take() {
+            Some(Command::Git(mut opts)) => {
+                opts.extend(args[split..].iter().cloned());
+                cli.command = Some(Command::Git(opts));
+            }
+            None => cli.git_args = args[split + 1..].to_vec(),
+            Some(_) => return Self::parse_from(args),
+        }
+
+        cli
+    }
+
+    async fn run(self, config: UserConfig) -> Result<()> {
+        let store_path = cluster_store_dir(self.store, config.store.clone())?;
+        let hooks = !self.no_hooks;
+        let selection = if self.all {
+            Selection::All
+        } else if self.deployed {
+            Selection::Deployed
+        } else {
+            Selection::Named
+        };
+
+        let command = match self.command {
+            Some(Command::Git(opts)) => Command::Git(opts),
+            None => Command::Git(self.git_args),
+            Some(_) if selection != Selection::Named => {
+                bail!("--all and --deployed only apply to Git commands, use `oxidot --all -- <git-command>` for Git commands named like Oxidot commands")
+            }
+            Some(command) => command,
+        };
+
+        match command {
+            Command::Init(opts) => run_init(&store_path, &config, opts),
+            Command::Clone(opts) => run_clone(&store_path, &config, hooks, opts).await,
+            Command::Deploy(opts) => run_deploy(&store_path, hooks, opts),
+            Command::Undeploy(opts) => run_undeploy(&store_path, hooks, opts),
+            Command::Status(opts) => run_status(&store_path, opts),
+            Command::Remove(opts) => run_remove(&store_path, hooks, opts),
+            Command::Doctor(opts) => run_doctor(&store_path, opts),
+            Command::Exec(opts) => run_exec(&store_path, opts),
+            Command::Shell(opts) => run_shell(&store_path, opts),
+            Command::Edit(opts) => run_edit(&store_path, hooks, opts),
+            Command::Config(opts) => run_config(&store_path, hooks, opts),
+            Command::Migrate(opts) => run_migrate(&store_path, opts),
+            Command::Schema => run_schema(),
+            Command::Dependency(opts) => run_dependency(&store_path, opts),
+            Command::Filter(opts) => run_filter(opts),
+            Command::Git(opts) => run_git(&store_path, &config, selection, opts),
         }
     }
 }
@@ -66,6 +162,46 @@ enum Command {
     #[command(override_usage = "oxidot remove [options] <cluster_name>")]
     Remove(RemoveOptions),
 
+    /// Check cluster store for problems.
+    #[command(override_usage = "oxidot doctor [options]")]
+    Doctor(DoctorOptions),
+
+    /// Run any program with target cluster as its Git repository.
+    #[command(override_usage = "oxidot exec [options] <cluster_name> -- <command>...")]
+    Exec(ExecOptions),
+
+    /// Start interactive shell with target cluster as its Git repository.
+    #[command(override_usage = "oxidot shell [options] <cluster_name>")]
+    Shell(ShellOptions),
+
+    /// Edit cluster definition of target cluster in text editor.
+    #[command(override_usage = "oxidot edit [options] <cluster_name>")]
+    Edit(EditOptions),
+
+    /// Get or change settings in cluster definition of target cluster.
+    #[command(
+        override_usage = "oxidot config [options] <cluster_name> (get | set | unset) <key> [<value>]"
+    )]
+    Config(ConfigOptions),
+
+    /// Add or remove dependencies in cluster definition of target cluster.
+    #[command(
+        override_usage = "oxidot dependency [options] <cluster_name> (add | remove) <name> [<url>]"
+    )]
+    Dependency(DependencyOptions),
+
+    /// Upgrade cluster definitions to current schema version.
+    #[command(override_usage = "oxidot migrate [options] [<cluster_name>]...")]
+    Migrate(MigrateOptions),
+
+    /// Print JSON Schema of cluster definitions for editors.
+    #[command(override_usage = "oxidot schema")]
+    Schema,
+
+    /// Encrypt, decrypt, or render tracked files as Git filter driver.
+    #[command(hide = true)]
+    Filter(FilterOptions),
+
     /// Run Git binary directly on target cluster.
     #[command(external_subcommand)]
     Git(Vec<OsString>),
@@ -106,6 +242,10 @@ struct CloneOptions {
     #[arg(required = true, value_name = "url")]
     pub url: String,
 
+    /// Mirror URL to fall back to if cloning from URL fails.
+    #[arg(short, long, value_name = "url")]
+    pub mirror: Vec<String>,
+
     /// Select branch to checkout.
     #[arg(short, long, value_name = "branch")]
     pub branch: Option<String>,
@@ -117,6 +257,10 @@ struct CloneOptions {
     /// Do not issue default deployment rules.
     #[arg(short = 'd', long)]
     pub no_default_deployment: bool,
+
+    /// Maximum number of dependencies to clone at the same time.
+    #[arg(short, long, value_name = "n")]
+    pub jobs: Option<usize>,
 }
 
 #[derive(Parser, Clone, Debug)]
@@ -137,6 +281,14 @@ struct DeployOptions {
     /// Deploy default set of tracked files to work tree alias.
     #[arg(short, long, group = "rules")]
     pub default: bool,
+
+    /// Deploy default set of tracked files adjusted by named include profile.
+    #[arg(short, long, group = "rules", value_name = "profile")]
+    pub profile: Vec<String>,
+
+    /// Deploy tracked files of named group.
+    #[arg(short, long, group = "rules", value_name = "group")]
+    pub group: Vec<String>,
 }
 
 #[derive(Parser, Clone, Debug)]
@@ -157,6 +309,15 @@ struct UndeployOptions {
     /// Undeploy default set of tracked files to work tree alias.
     #[arg(short, long, group = "rules")]
     pub default: bool,
+
+    /// Undeploy default set of tracked files adjusted by named include
+    /// profile.
+    #[arg(short, long, group = "rules", value_name = "profile")]
+    pub profile: Vec<String>,
+
+    /// Undeploy tracked files of named group.
+    #[arg(short, long, group = "rules", value_name = "group")]
+    pub group: Vec<String>,
 }
 
 #[derive(Parser, Clone, Debug)]
@@ -169,6 +330,10 @@ struct StatusOptions {
     #[arg(group = "target", short, long, value_name = "cluster_name")]
     pub files: Option<String>,
 
+    /// Show deployment state of named groups of target cluster.
+    #[arg(group = "target", short, long, value_name = "cluster_name")]
+    pub groups: Option<String>,
+
     /// List only deployed clusters.
     #[arg(group = "target", short, long)]
     pub deployed: bool,
@@ -186,22 +351,197 @@ struct RemoveOptions {
     pub cluster_names: Vec<String>,
 }
 
+#[derive(Parser, Clone, Debug)]
+#[command(author, about, long_about)]
+struct DoctorOptions {
+    /// Repair problems that are safe to fix automatically.
+    #[arg(short, long)]
+    pub fix: bool,
+}
+
+#[derive(Parser, Clone, Debug)]
+#[command(author, about, long_about)]
+struct ExecOptions {
+    /// Name of cluster to run program on.
+    #[arg(required = true, value_name = "cluster_name")]
+    pub cluster_name: String,
+
+    /// Program to run along with its arguments.
+    #[arg(last = true, required = true, value_name = "command")]
+    pub command: Vec<OsString>,
+}
+
+#[derive(Parser, Clone, Debug)]
+#[command(author, about, long_about)]
+struct ShellOptions {
+    /// Name of cluster to start shell on.
+    #[arg(required = true, value_name = "cluster_name")]
+    pub cluster_name: String,
+}
+
+#[derive(Parser, Clone, Debug)]
+#[command(author, about, long_about)]
+struct EditOptions {
+    /// Name of cluster whose cluster definition is edited.
+    #[arg(required = true, value_name = "cluster_name")]
+    pub cluster_name: String,
+}
+
+#[derive(Parser, Clone, Debug)]
+#[command(author, about, long_about)]
+struct ConfigOptions {
+    /// Name of cluster whose cluster definition is used.
+    #[arg(required = true, value_name = "cluster_name")]
+    pub cluster_name: String,
+
+    #[command(subcommand)]
+    pub action: ConfigAction,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+enum ConfigAction {
+    /// Print value of key.
+    Get {
+        /// Dotted key relative to settings, e.g., "remote.url".
+        #[arg(value_name = "key")]
+        key: String,
+    },
+
+    /// Set key to value, where value is parsed as TOML if possible.
+    Set {
+        /// Dotted key relative to settings, e.g., "remote.url".
+        #[arg(value_name = "key")]
+        key: String,
+
+        /// New value of key.
+        #[arg(value_name = "value")]
+        value: String,
+    },
+
+    /// Remove key along with its value.
+    Unset {
+        /// Dotted key relative to settings, e.g., "remote.url".
+        #[arg(value_name = "key")]
+        key: String,
+    },
+}
+
+#[derive(Parser, Clone, Debug)]
+#[command(author, about, long_about)]
+struct DependencyOptions {
+    /// Name of cluster whose cluster definition is used.
+    #[arg(required = true, value_name = "cluster_name")]
+    pub cluster_name: String,
+
+    #[command(subcommand)]
+    pub action: DependencyAction,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+enum DependencyAction {
+    /// Add dependency to cluster definition.
+    Add {
+        /// Name of dependency in cluster store.
+        #[arg(value_name = "name")]
+        name: String,
+
+        /// URL of remote to clone dependency from.
+        #[arg(value_name = "url")]
+        url: String,
+
+        /// Target branch to use instead of the default branch.
+        #[arg(short, long, value_name = "branch")]
+        branch: Option<String>,
+
+        /// Mirror URL to fall back to if cloning from URL fails.
+        #[arg(short, long, value_name = "url")]
+        mirror: Vec<String>,
+
+        /// Sparsity rule of dependency to deploy along with cluster.
+        #[arg(short, long, value_name = "sparsity_rule")]
+        include: Vec<String>,
+    },
+
+    /// Remove dependency from cluster definition.
+    Remove {
+        /// Name of dependency in cluster store.
+        #[arg(value_name = "name")]
+        name: String,
+    },
+}
+
+#[derive(Parser, Clone, Debug)]
+#[command(author, about, long_about)]
+struct MigrateOptions {
+    /// Name of cluster to migrate, every cluster if none are given.
+    #[arg(value_name = "cluster_name")]
+    pub cluster_names: Vec<String>,
+}
+
+#[derive(Parser, Clone, Debug)]
+#[command(author, about, long_about)]
+struct FilterOptions {
+    /// Filter driver to run.
+    #[arg(value_enum, value_name = "driver")]
+    pub driver: FilterDriver,
+
+    /// Filter operation that Git requests.
+    #[arg(value_enum, value_name = "mode")]
+    pub mode: FilterMode,
+
+    /// Path to gitdir of cluster.
+    #[arg(value_name = "gitdir")]
+    pub gitdir: PathBuf,
+
+    /// Path of tracked file relative to top-level of cluster, or path of file
+    /// to read for textconv.
+    #[arg(value_name = "path")]
+    pub path: PathBuf,
+}
+
+/// Filter drivers that Oxidot implements.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum FilterDriver {
+    /// Encrypt and decrypt tracked files.
+    Crypt,
+
+    /// Render templates.
+    Template,
+}
+
+/// Filter operations of Git filter drivers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum FilterMode {
+    /// Convert content from work tree alias for storage in cluster.
+    Clean,
+
+    /// Convert content from cluster for deployment.
+    Smudge,
+
+    /// Convert content of file for diffs.
+    Textconv,
+}
+
 #[tokio::main]
 async fn main() {
-    let layer = fmt::layer()
-        .compact()
-        .with_target(false)
-        .with_timer(false)
-        .without_time();
+    let cli = Cli::parse_with_passthrough();
+    let config = load_user_config();
+
+    // INVARIANT: Output format must be known before any output is produced.
+    let output = config
+        .as_ref()
+        .ok()
+        .and_then(|config| config.output)
+        .unwrap_or_default();
     let filter = EnvFilter::try_from_default_env()
         .or_else(|_| EnvFilter::try_new("info"))
         .unwrap();
     tracing_subscriber::registry()
-        .with(layer)
+        .with(output_layer(output))
         .with(filter)
         .init();
 
-    if let Err(error) = run().await {
+    if let Err(error) = run(cli, config).await {
         error!("{error:?}");
         exit(1);
     }
@@ -209,34 +549,71 @@ async fn main() {
     exit(0)
 }
 
-async fn run() -> Result<()> {
-    Cli::parse().run().await
+async fn run(cli: Cli, config: Result<UserConfig>) -> Result<()> {
+    cli.run(config?).await
+}
+
+fn output_layer(output: OutputFormat) -> Box<dyn Layer<Registry> + Send + Sync> {
+    match output {
+        OutputFormat::Compact => fmt::layer()
+            .compact()
+            .with_target(false)
+            .without_time()
+            .boxed(),
+        OutputFormat::Pretty => fmt::layer()
+            .pretty()
+            .with_target(false)
+            .without_time()
+            .boxed(),
+        OutputFormat::Full => fmt::layer().boxed(),
+    }
+}
+
+fn load_user_config() -> Result<UserConfig> {
+    let path = user_config_file()?;
+    match read_to_string(&path) {
+        Ok(data) => Ok(data.parse()?),
+        Err(error) if error.kind() == ErrorKind::NotFound => Ok(UserConfig::default()),
+        Err(error) => Err(error.into()),
+    }
 }
 
-fn run_init(opts: InitOptions) -> Result<()> {
+fn run_init(store_path: &Path, config: &UserConfig, opts: InitOptions) -> Result<()> {
     let mut definition = ClusterDefinition::default();
     definition.settings.description = match opts.description {
         Some(description) => description,
-        None => "<put one sentence description here>".into(),
+        None => PLACEHOLDER_DESCRIPTION.into(),
     };
     definition.settings.remote.url = match opts.url {
         Some(url) => url,
-        None => "<put url to remote here>".into(),
+        None => PLACEHOLDER_URL.into(),
     };
     definition.settings.remote.branch = opts.branch;
-    definition.settings.work_tree_alias = match opts.work_tree_alias {
-        Some(path) => WorkTreeAlias::new(path),
-        None => WorkTreeAlias::new(home_dir()?),
+    definition.settings.work_tree_alias = match (opts.work_tree_alias, &config.work_tree_alias) {
+        (Some(path), _) => WorkTreeAlias::new(path),
+        (None, Some(work_tree_alias)) => work_tree_alias.clone(),
+        (None, None) => WorkTreeAlias::new(home_dir()?),
     };
 
-    let store = Store::open(default_cluster_store_dir()?)?;
-    store.init_cluster(opts.cluster_name, definition)?;
+    let store = Store::open(store_path)?;
+    store.init_cluster(&opts.cluster_name, definition)?;
+    store.use_cluster(&opts.cluster_name, |cluster| {
+        cluster.set_sparse_mode(config.sparse_mode.unwrap_or_default())?;
+        Ok(())
+    })?;
 
     Ok(())
 }
 
-async fn run_clone(opts: CloneOptions) -> Result<()> {
-    let store = Store::open(default_cluster_store_dir()?)?;
+async fn run_clone(
+    store_path: &Path,
+    config: &UserConfig,
+    hooks: bool,
+    opts: CloneOptions,
+) -> Result<()> {
+    let store = Store::open(store_path)?;
+    store.set_hooks_enabled(hooks);
+    store.set_url_rewrites(config.url_rewrites.clone().unwrap_or_default());
 
     let branch = if let Some(branch) = opts.branch {
         BranchTarget::Target(branch)
@@ -244,9 +621,22 @@ async fn run_clone(opts: CloneOptions) -> Result<()> {
         BranchTarget::Default
     };
 
-    store.clone_cluster(&opts.cluster_name, &opts.url, branch)?;
+    let sparse_mode = config.sparse_mode.unwrap_or_default();
+    let urls = std::iter::once(&opts.url).chain(&opts.mirror);
+    store.clone_cluster(&opts.cluster_name, urls, branch)?;
+    store.use_cluster(&opts.cluster_name, |cluster| {
+        cluster.set_sparse_mode(sparse_mode)?;
+        Ok(())
+    })?;
+
     if !opts.no_dependencies {
-        store.resolve_dependencies(&opts.cluster_name).await?;
+        store
+            .resolve_dependencies(&opts.cluster_name, opts.jobs.or(config.jobs))
+            .await?;
+        store.use_cluster_dependencies(&opts.cluster_name, |cluster| {
+            cluster.set_sparse_mode(sparse_mode)?;
+            Ok(())
+        })?;
     }
 
     if !opts.no_default_deployment {
@@ -259,13 +649,20 @@ async fn run_clone(opts: CloneOptions) -> Result<()> {
     Ok(())
 }
 
-fn run_deploy(opts: DeployOptions) -> Result<()> {
-    let store = Store::open(default_cluster_store_dir()?)?;
+fn run_deploy(store_path: &Path, hooks: bool, opts: DeployOptions) -> Result<()> {
+    let store = Store::open(store_path)?;
+    store.set_hooks_enabled(hooks);
     store.use_cluster(opts.cluster_name, |cluster| {
         if opts.all {
             cluster.deploy_all()?;
         } else if opts.default {
             cluster.deploy_default_rules()?;
+        } else if !opts.profile.is_empty() {
+            cluster.deploy_profile_rules(&ProfileSelection::Named(opts.profile))?;
+        } else if !opts.group.is_empty() {
+            for group in opts.group {
+                cluster.deploy_group(group)?;
+            }
         } else {
             cluster.deploy_with_rules(opts.sparsity_rules)?;
         }
@@ -276,13 +673,20 @@ fn run_deploy(opts: DeployOptions) -> Result<()> {
     Ok(())
 }
 
-fn run_undeploy(opts: UndeployOptions) -> Result<()> {
-    let store = Store::open(default_cluster_store_dir()?)?;
+fn run_undeploy(store_path: &Path, hooks: bool, opts: UndeployOptions) -> Result<()> {
+    let store = Store::open(store_path)?;
+    store.set_hooks_enabled(hooks);
     store.use_cluster(opts.cluster_name, |cluster| {
         if opts.all {
             cluster.undeploy_all()?;
         } else if opts.default {
             cluster.undeploy_default_rules()?;
+        } else if !opts.profile.is_empty() {
+            cluster.undeploy_profile_rules(&ProfileSelection::Named(opts.profile))?;
+        } else if !opts.group.is_empty() {
+            for group in opts.group {
+                cluster.undeploy_group(group)?;
+            }
         } else {
             cluster.undeploy_with_rules(opts.sparsity_rules)?;
         }
@@ -293,25 +697,28 @@ fn run_undeploy(opts: UndeployOptions) -> Result<()> {
     Ok(())
 }
 
-fn run_status(opts: StatusOptions) -> Result<()> {
-    let store = Store::open(default_cluster_store_dir()?)?;
+fn run_status(store_path: &Path, opts: StatusOptions) -> Result<()> {
+    let store = Store::open_shared(store_path)?;
     if let Some(cluster_name) = opts.sparsity_rules {
         store.tracked_files_status(cluster_name)?;
     } else if let Some(cluster_name) = opts.files {
         store.deploy_rules_status(cluster_name)?;
+    } else if let Some(cluster_name) = opts.groups {
+        store.groups_status(cluster_name)?;
     } else if opts.deployed {
-        store.deployed_only_status();
+        store.deployed_only_status()?;
     } else if opts.undeployed {
-        store.undeployed_only_status();
+        store.undeployed_only_status()?;
     } else {
-        store.detailed_status();
+        store.detailed_status()?;
     }
 
     Ok(())
 }
 
-fn run_remove(opts: RemoveOptions) -> Result<()> {
-    let store = Store::open(default_cluster_store_dir()?)?;
+fn run_remove(store_path: &Path, hooks: bool, opts: RemoveOptions) -> Result<()> {
+    let store = Store::open(store_path)?;
+    store.set_hooks_enabled(hooks);
     for cluster_name in opts.cluster_names {
         store.remove_cluster(cluster_name)?;
     }
@@ -319,12 +726,357 @@ fn run_remove(opts: RemoveOptions) -> Result<()> {
     Ok(())
 }
 
-fn run_git(opts: Vec<OsString>) -> Result<()> {
-    let store = Store::open(default_cluster_store_dir()?)?;
-    let target = opts[0].to_string_lossy().into_owned();
-    store.use_cluster(target, |cluster| {
-        cluster.gitcall_interactive(opts[1..].to_vec())?;
-        Ok(())
+fn run_doctor(store_path: &Path, opts: DoctorOptions) -> Result<()> {
+    let mode = if opts.fix {
+        LockMode::Exclusive
+    } else {
+        LockMode::Shared
+    };
+    create_dir_all(store_path)?;
+    let _lock = StoreLock::acquire(store_path, mode)?;
+
+    let findings = doctor::examine(store_path)?;
+    if findings.is_empty() {
+        info!("cluster store is healthy");
+        return Ok(());
+    }
+
+    let mut unresolved = 0;
+    for finding in findings {
+        if opts.fix && finding.repair()? {
+            info!("fixed {finding}");
+            continue;
+        }
+
+        let hint = if finding.is_fixable() {
+            " (fixable with --fix)"
+        } else {
+            ""
+        };
+        match finding.severity() {
+            Severity::Warning => warn!("{finding}{hint}"),
+            Severity::Error => {
+                error!("{finding}{hint}");
+                unresolved += 1;
+            }
+        }
+    }
+
+    if unresolved != 0 {
+        bail!("cluster store has {unresolved} unresolved error(s)");
+    }
+
+    Ok(())
+}
+
+fn run_exec(store_path: &Path, opts: ExecOptions) -> Result<()> {
+    Store::exec_cluster(
+        store_path,
+        &opts.cluster_name,
+        &opts.command[0],
+        opts.command[1..].to_vec(),
+    )?;
+
+    Ok(())
+}
+
+fn run_shell(store_path: &Path, opts: ShellOptions) -> Result<()> {
+    let shell = match std::env::var_os("SHELL") {
+        Some(shell) if !shell.is_empty() => shell,
+        _ if cfg!(windows) => "cmd".into(),
+        _ => "sh".into(),
+    };
+
+    info!(
+        "start shell for cluster {}, exit to return",
+        opts.cluster_name
+    );
+    Store::exec_cluster(
+        store_path,
+        &opts.cluster_name,
+        &shell,
+        Vec::<OsString>::new(),
+    )?;
+
+    Ok(())
+}
+
+fn run_edit(store_path: &Path, hooks: bool, opts: EditOptions) -> Result<()> {
+    let editor = match std::env::var_os("VISUAL").or_else(|| std::env::var_os("EDITOR")) {
+        Some(editor) if !editor.is_empty() => editor,
+        _ if cfg!(windows) => "notepad".into(),
+        _ => "vi".into(),
+    };
+
+    // INVARIANT: Never hold the store lock while the editor runs.
+    let name = &opts.cluster_name;
+    let (source, path) = Store::open_shared(store_path)?.use_cluster(name, |cluster| {
+        Ok((
+            cluster.definition_source()?,
+            cluster.gitdir().join("EDIT_cluster.toml"),
+        ))
     })?;
+
+    let result = edit_until_valid(&editor, &path, &source);
+    if let Err(error) = remove_file(&path) {
+        if error.kind() != ErrorKind::NotFound {
+            warn!("cannot remove {:?}: {error}", path.display());
+        }
+    }
+    let contents = result?;
+
+    let store = Store::open(store_path)?;
+    store.set_hooks_enabled(hooks);
+    edit_definition(&store, name, "chore: edit cluster.toml".into(), |editor| {
+        // INVARIANT: Never clobber changes made while the editor ran.
+        if editor.to_string() != source {
+            return Err(EditError::Changed.into());
+        }
+
+        *editor = contents.parse()?;
+        Ok(())
+    })
+}
+
+fn edit_until_valid(editor: &OsString, path: &Path, source: &str) -> Result<String> {
+    // INVARIANT: Keep editing until cluster definition is valid, or emptied.
+    let mut contents = source.to_string();
+    let mut problem = None;
+    loop {
+        write(path, annotate_problem(problem.as_deref(), &contents))?;
+        run_editor(editor, path)?;
+        contents = strip_problem(&read_to_string(path)?);
+        if contents.trim().is_empty() {
+            bail!("cluster definition is empty, edit aborted");
+        }
+
+        match contents.parse::<ClusterDefinition>() {
+            Ok(_) => return Ok(contents),
+            Err(error) => {
+                warn!("invalid cluster definition: {error}");
+                problem = Some(error.to_string());
+            }
+        }
+    }
+}
+
+fn run_editor(editor: &OsString, path: &Path) -> Result<()> {
+    // INVARIANT: Editors like "code --wait" need their arguments split by the shell.
+    let status = if cfg!(unix) {
+        std::process::Command::new("sh")
+            .arg("-c")
+            .arg(format!(r#"{} "$@""#, editor.to_string_lossy()))
+            .arg(editor)
+            .arg(path)
+            .status()?
+    } else {
+        std::process::Command::new(editor).arg(path).status()?
+    };
+    if !status.success() {
+        bail!("editor {editor:?} failed, edit aborted");
+    }
+
+    Ok(())
+}
+
+fn run_config(store_path: &Path, hooks: bool, opts: ConfigOptions) -> Result<()> {
+    let name = &opts.cluster_name;
+    if let ConfigAction::Get { key } = &opts.action {
+        let store = Store::open_shared(store_path)?;
+        let source = store.use_cluster(name, |cluster| Ok(cluster.definition_source()?))?;
+        let value = source.parse::<DefinitionEditor>()?.get(key)?;
+        writeln!(stdout(), "{value}")?;
+        return Ok(());
+    }
+
+    let store = Store::open(store_path)?;
+    store.set_hooks_enabled(hooks);
+    match opts.action {
+        ConfigAction::Get { .. } => (),
+        ConfigAction::Set { key, value } => {
+            let message = format!("chore: set {} in cluster.toml", normalize_key(&key)?);
+            edit_definition(
+                &store,
+                name,
+                message,
+                |editor| Ok(editor.set(&key, &value)?),
+            )?;
+        }
+        ConfigAction::Unset { key } => {
+            let message = format!("chore: unset {} in cluster.toml", normalize_key(&key)?);
+            edit_definition(&store, name, message, |editor| Ok(editor.unset(&key)?))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn run_dependency(store_path: &Path, opts: DependencyOptions) -> Result<()> {
+    let name = &opts.cluster_name;
+    match opts.action {
+        DependencyAction::Add {
+            name: dependency,
+            url,
+            branch,
+            mirror,
+            include,
+        } => {
+            let message = format!("chore: add dependency {dependency}");
+            let dependency = ClusterDependency {
+                name: dependency,
+                remote: ClusterRemote {
+                    url,
+                    branch,
+                    mirrors: (!mirror.is_empty()).then_some(mirror),
+                },
+                include: (!include.is_empty()).then_some(include),
+            };
+            edit_definition(&Store::open(store_path)?, name, message, |editor| {
+                Ok(editor.add_dependency(&dependency)?)
+            })?;
+        }
+        DependencyAction::Remove { name: dependency } => {
+            let message = format!("chore: remove dependency {dependency}");
+            edit_definition(&Store::open(store_path)?, name, message, |editor| {
+                Ok(editor.remove_dependency(&dependency)?)
+            })?;
+        }
+    }
+
+    Ok(())
+}
+
+fn run_migrate(store_path: &Path, opts: MigrateOptions) -> Result<()> {
+    let store = Store::open(store_path)?;
+    let names = if opts.cluster_names.is_empty() {
+        store.cluster_names()
+    } else {
+        opts.cluster_names
+    };
+
+    let message = format!("chore: migrate cluster.toml to schema version {SCHEMA_VERSION}");
+    for name in names {
+        let source = store.use_cluster(&name, |cluster| Ok(cluster.definition_source()?))?;
+        let Some(migrated) = migrate(&source)? else {
+            info!("cluster {name} is already at schema version {SCHEMA_VERSION}");
+            continue;
+        };
+
+        edit_definition(&store, &name, message.clone(), |editor| {
+            *editor = migrated.parse()?;
+            Ok(())
+        })?;
+        info!("migrated cluster {name} to schema version {SCHEMA_VERSION}");
+    }
+
+    Ok(())
+}
+
+fn run_schema() -> Result<()> {
+    let schema = serde_json::to_string_pretty(&cluster_definition_schema())?;
+    writeln!(stdout(), "{schema}")?;
+
+    Ok(())
+}
+
+fn edit_definition(
+    store: &Store,
+    name: &str,
+    message: String,
+    edit: impl FnOnce(&mut DefinitionEditor) -> oxidot::store::Result<()>,
+) -> Result<()> {
+    if !store.edit_definition(name, message, edit)? {
+        info!("cluster definition of {name} is unchanged");
+    }
+
+    Ok(())
+}
+
+fn run_filter(opts: FilterOptions) -> Result<()> {
+    // INVARIANT: Filters only read the cluster, so they never take the store lock.
+    let output = match opts.driver {
+        FilterDriver::Crypt => {
+            let filter = CryptFilter::new(opts.gitdir);
+            match opts.mode {
+                FilterMode::Clean => filter.clean(&opts.path, &read_stdin()?)?,
+                FilterMode::Smudge => filter.smudge(&read_stdin()?)?,
+                FilterMode::Textconv => filter.textconv(&opts.path)?,
+            }
+        }
+        FilterDriver::Template => {
+            let filter = TemplateFilter::new(opts.gitdir);
+            match opts.mode {
+                FilterMode::Clean => filter.clean(&opts.path, &read_stdin()?)?,
+                FilterMode::Smudge => filter.smudge(&read_stdin()?)?,
+                FilterMode::Textconv => bail!("template filter does not support textconv"),
+            }
+        }
+    };
+
+    let mut stdout = stdout().lock();
+    stdout.write_all(&output)?;
+    stdout.flush()?;
+
+    Ok(())
+}
+
+fn read_stdin() -> Result<Vec<u8>> {
+    let mut input = Vec::new();
+    stdin().read_to_end(&mut input)?;
+    Ok(input)
+}
+
+fn run_git(
+    store_path: &Path,
+    config: &UserConfig,
+    selection: Selection,
+    opts: Vec<OsString>,
+) -> Result<()> {
+    let store = Store::open(store_path)?;
+    let (targets, git_args) = store.select_clusters(selection, &opts)?;
+
+    if targets.is_empty() {
+        match (selection, opts.first()) {
+            (Selection::Named, Some(name)) => {
+                bail!(
+                    "cluster {:?} not found in cluster store",
+                    name.to_string_lossy()
+                )
+            }
+            (Selection::Named, None) => bail!("no cluster given to run Git command on"),
+            _ => {
+                warn!("no clusters selected to run Git command on");
+                return Ok(());
+            }
+        }
+    }
+
+    if git_args.is_empty() {
+        bail!("no Git command given");
+    }
+
+    let mut args = Vec::new();
+    if let Some(pager) = &config.pager {
+        args.extend(["-c".into(), format!("core.pager={pager}").into()]);
+    }
+    args.extend(git_args);
+
+    if targets.len() == 1 {
+        store.use_cluster(&targets[0], |cluster| {
+            cluster.gitcall_interactive(args)?;
+            Ok(())
+        })?;
+
+        return Ok(());
+    }
+
+    store
+        .use_each_cluster(&targets, |name, cluster| {
+            info!("cluster {name}:");
+            cluster.gitcall_interactive(args.clone())?;
+            Ok(())
+        })
+        .context("Git command failed")?;
+
     Ok(())
 }
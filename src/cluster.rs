@@ -43,16 +43,38 @@
 //!
 //! 1. [`ClusterDefinition`]
 //! 2. [`sparse`]
+//! 3. [`passthrough`]
+//! 4. [`guard`]
+//! 5. [`secrets`]
+//! 6. [`filter`]
+//! 7. [`crypt`]
+//! 8. [`template`]
+//! 9. [`profile`]
+//! 10. [`hooks`]
+//! 11. [`permissions`]
 
+pub mod crypt;
 pub mod deploy;
+pub mod filter;
+pub mod guard;
+pub mod hooks;
+pub mod passthrough;
+pub mod permissions;
+pub mod profile;
+pub mod secrets;
 pub mod sparse;
+pub mod template;
 
 use crate::{
     cluster::{
-        deploy::{Deployment, Git2Deployer},
+        deploy::{Deployment, Error as DeployError, Git2Deployer, Result as DeployResult},
+        guard::{AddGuard, ADD_COMMANDS, DEFAULT_ADD_THRESHOLD, IGNORE_FILE},
+        hooks::{HookKind, TRUST_KEY},
+        permissions::Drift,
+        profile::ProfileSelection,
         sparse::{InvertedGitignore, SparsityDrafter},
     },
-    config::ClusterDefinition,
+    config::{ClusterDefinition, ClusterSettings, SparseMode, UrlRewrites},
 };
 
 use auth_git2::{GitAuthenticator, Prompter};
@@ -60,8 +82,11 @@
 use indicatif::{ProgressBar, ProgressStyle};
 use inquire::{Password, Text};
 use std::{
-    ffi::OsString,
+    collections::HashSet,
+    ffi::{OsStr, OsString},
+    fmt::{Display, Formatter, Result as FmtResult},
     path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
     time,
 };
 use tracing::{debug, info, instrument, warn};
@@ -73,6 +98,9 @@
 /// essential files in a target directory labeled as a work tree alias, without
 /// needing to initialize it as a Git repository. Tracked files can be deployed
 /// or undeployed to the work tree alias at will.
+///
+/// Deployment hooks of the cluster definition run around every deployment
+/// and undeployment, see [`hooks`].
 #[derive(Debug)]
 pub struct Cluster<D = Git2Deployer>
 where
@@ -80,6 +108,7 @@ pub struct Cluster<D = Git2Deployer>
 {
     pub(crate) definition: ClusterDefinition,
     pub(crate) deployer: D,
+    pub(crate) hooks: AtomicBool,
 }
 
 impl<D> Cluster<D>
@@ -91,23 +120,34 @@ pub fn new(definition: ClusterDefinition, deployer: D) -> Self {
         Self {
             definition,
             deployer,
+            hooks: AtomicBool::new(true),
         }
     }
 
+    /// Enable or disable deployment hooks of cluster.
+    ///
+    /// Hooks are enabled by default.
+    pub fn set_hooks_enabled(&self, enabled: bool) {
+        self.hooks.store(enabled, Ordering::Relaxed);
+    }
+
     /// Deploy tracked files to work tree alias that match sparsity rules.
     ///
     /// Adds target rules to match tracked files, and updates cluster's index.
     ///
     /// # Errors
     ///
+    /// - Return [`Error::Hooks`] if deployment hooks fail, or are not trusted.
     /// - Return [`Error::Deployment`] if deployment logic fails.
     pub fn deploy_with_rules(
         &self,
         rules: impl IntoIterator<Item = impl Into<String>>,
     ) -> Result<()> {
-        Ok(self
-            .deployer
-            .deploy_with_rules(&self.definition.settings.work_tree_alias, rules)?)
+        self.with_hooks(HookKind::PreDeploy, HookKind::PostDeploy, || {
+            Ok(self
+                .deployer
+                .deploy_with_rules(&self.definition.settings.work_tree_alias, rules)?)
+        })
     }
 
     /// Undeploy tracked files from work tree alias that match sparsity rules.
@@ -117,30 +157,53 @@ pub fn deploy_with_rules(
     ///
     /// # Errors
     ///
+    /// - Return [`Error::Hooks`] if deployment hooks fail, or are not trusted.
     /// - Return [`Error::Deployment`] if deployment logic fails.
     pub fn undeploy_with_rules(
         &self,
         rules: impl IntoIterator<Item = impl AsRef<str>>,
     ) -> Result<()> {
-        Ok(self
-            .deployer
-            .undeploy_with_rules(&self.definition.settings.work_tree_alias, rules)?)
+        self.with_hooks(HookKind::PreUndeploy, HookKind::PostUndeploy, || {
+            Ok(self
+                .deployer
+                .undeploy_with_rules(&self.definition.settings.work_tree_alias, rules)?)
+        })
     }
 
     /// Deploy tracked files based on default set of spasrity rules provided by
     /// cluster definition.
     ///
     /// Clears current sparsity rules, and replaces them with default
-    /// sparsity rules from cluster definition.
+    /// sparsity rules from cluster definition. Include profiles that match
+    /// the current machine adjust the default sparsity rules.
     ///
     /// # Errors
     ///
+    /// - Return [`Error::Hooks`] if deployment hooks fail, or are not trusted.
     /// - Return [`Error::Deployment`] if deployment logic fails.
     pub fn deploy_default_rules(&self) -> Result<()> {
+        self.deploy_profile_rules(&ProfileSelection::detect())
+    }
+
+    /// Deploy tracked files based on default set of sparsity rules adjusted
+    /// by selected include profiles.
+    ///
+    /// Clears current sparsity rules, and replaces them with the adjusted
+    /// default sparsity rules.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::Profile`] if a profile selected by name does not
+    ///   exist.
+    /// - Return [`Error::Hooks`] if deployment hooks fail, or are not trusted.
+    /// - Return [`Error::Deployment`] if deployment logic fails.
+    pub fn deploy_profile_rules(&self, selection: &ProfileSelection) -> Result<()> {
         let work_tree_alias = &self.definition.settings.work_tree_alias;
-        if let Some(default) = &self.definition.settings.include {
-            self.deployer.undeploy_all(work_tree_alias)?;
-            self.deployer.deploy_with_rules(work_tree_alias, default)?;
+        if let Some(rules) = selection.default_rules(&self.definition.settings)? {
+            self.with_hooks(HookKind::PreDeploy, HookKind::PostDeploy, || {
+                self.deployer.undeploy_all(work_tree_alias)?;
+                Ok(self.deployer.deploy_with_rules(work_tree_alias, rules)?)
+            })?;
         }
 
         Ok(())
@@ -150,21 +213,131 @@ pub fn deploy_default_rules(&self) -> Result<()> {
     /// by cluster definition.
     ///
     /// Removes only the rules that match the default sparsity rules provided
-    /// by the cluster definition.
+    /// by the cluster definition, as adjusted by include profiles that match
+    /// the current machine.
     ///
     /// # Errors
     ///
+    /// - Return [`Error::Hooks`] if deployment hooks fail, or are not trusted.
     /// - Return [`Error::Deployment`] if deployment logic fails.
     pub fn undeploy_default_rules(&self) -> Result<()> {
+        self.undeploy_profile_rules(&ProfileSelection::detect())
+    }
+
+    /// Undeploy tracked files based on default set of sparsity rules adjusted
+    /// by selected include profiles.
+    ///
+    /// Removes only the rules that match the adjusted default sparsity rules.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::Profile`] if a profile selected by name does not
+    ///   exist.
+    /// - Return [`Error::Hooks`] if deployment hooks fail, or are not trusted.
+    /// - Return [`Error::Deployment`] if deployment logic fails.
+    pub fn undeploy_profile_rules(&self, selection: &ProfileSelection) -> Result<()> {
         let work_tree_alias = &self.definition.settings.work_tree_alias;
-        if let Some(default) = &self.definition.settings.include {
-            self.deployer
-                .undeploy_with_rules(work_tree_alias, default)?;
+        if let Some(rules) = selection.default_rules(&self.definition.settings)? {
+            self.with_hooks(HookKind::PreUndeploy, HookKind::PostUndeploy, || {
+                Ok(self.deployer.undeploy_with_rules(work_tree_alias, rules)?)
+            })?;
         }
 
         Ok(())
     }
 
+    /// Deploy tracked files that match sparsity rules of named group.
+    ///
+    /// Adds rules of group to current sparsity rules, and updates cluster's
+    /// index.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::GroupNotFound`] if cluster definition has no group
+    ///   with that name.
+    /// - Return [`Error::Hooks`] if deployment hooks fail, or are not trusted.
+    /// - Return [`Error::Deployment`] if deployment logic fails.
+    pub fn deploy_group(&self, name: impl AsRef<str>) -> Result<()> {
+        let rules = self.group_rules(name.as_ref())?;
+        self.deploy_with_rules(rules)
+    }
+
+    /// Undeploy tracked files that match sparsity rules of named group.
+    ///
+    /// Removes rules of group from current sparsity rules, and updates
+    /// cluster's index. Rules that another deployed group, or the deployed
+    /// default sparsity rules still need are kept.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::GroupNotFound`] if cluster definition has no group
+    ///   with that name.
+    /// - Return [`Error::Hooks`] if deployment hooks fail, or are not trusted.
+    /// - Return [`Error::Deployment`] if deployment logic fails.
+    pub fn undeploy_group(&self, name: impl AsRef<str>) -> Result<()> {
+        let rules = self.group_rules(name.as_ref())?;
+        let current = self.list_deploy_rules()?;
+        let is_deployed = |rules: &[String]| rules.iter().all(|rule| current.contains(rule));
+
+        let settings = &self.definition.settings;
+        let mut needed = settings
+            .groups
+            .iter()
+            .flatten()
+            .filter(|(group, rules)| *group != name.as_ref() && is_deployed(rules))
+            .flat_map(|(_, rules)| rules.iter())
+            .cloned()
+            .collect::<Vec<_>>();
+        let defaults = ProfileSelection::detect()
+            .default_rules(settings)?
+            .unwrap_or_default();
+        if is_deployed(&defaults) {
+            needed.extend(defaults);
+        }
+
+        let removed = rules.iter().filter(|rule| !needed.contains(rule));
+        self.undeploy_with_rules(removed)
+    }
+
+    /// Determine deployment state of every named group.
+    ///
+    /// Based on which rules of each group are in current sparsity rules.
+    /// Groups are sorted by name.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::Deployment`] if deployment logic fails.
+    pub fn group_status(&self) -> Result<Vec<(String, GroupState)>> {
+        let current = self.list_deploy_rules()?;
+        let status = self
+            .definition
+            .settings
+            .groups
+            .iter()
+            .flatten()
+            .map(|(name, rules)| {
+                let deployed = rules.iter().filter(|rule| current.contains(rule)).count();
+                let state = match deployed {
+                    0 => GroupState::Undeployed,
+                    count if count == rules.len() => GroupState::Deployed,
+                    _ => GroupState::Partial,
+                };
+                (name.clone(), state)
+            })
+            .collect();
+
+        Ok(status)
+    }
+
+    fn group_rules(&self, name: &str) -> Result<&Vec<String>> {
+        self.definition
+            .settings
+            .groups
+            .as_ref()
+            .and_then(|groups| groups.get(name))
+            .ok_or_else(|| Error::GroupNotFound { name: name.into() })
+    }
+
     /// Deploy all tracked files of cluster to work tree alias.
     ///
     /// Replaces entire sparsity rule set with one rule: "/*". Applies this
@@ -172,11 +345,14 @@ pub fn undeploy_default_rules(&self) -> Result<()> {
     ///
     /// # Errors
     ///
+    /// - Return [`Error::Hooks`] if deployment hooks fail, or are not trusted.
     /// - Return [`Error::Deployment`] if deployment logic fails.
     pub fn deploy_all(&self) -> Result<()> {
-        Ok(self
-            .deployer
-            .deploy_all(&self.definition.settings.work_tree_alias)?)
+        self.with_hooks(HookKind::PreDeploy, HookKind::PostDeploy, || {
+            Ok(self
+                .deployer
+                .deploy_all(&self.definition.settings.work_tree_alias)?)
+        })
     }
 
     /// Undeploy all tracked files of cluster from work tree alias.
@@ -190,10 +366,83 @@ pub fn deploy_all(&self) -> Result<()> {
     ///
     /// # Errors
     ///
+    /// - Return [`Error::Hooks`] if deployment hooks fail, or are not trusted.
+    /// - Return [`Error::Deployment`] if deployment logic fails.
     pub fn undeploy_all(&self) -> Result<()> {
-        Ok(self
-            .deployer
-            .undeploy_all(&self.definition.settings.work_tree_alias)?)
+        self.with_hooks(HookKind::PreUndeploy, HookKind::PostUndeploy, || {
+            Ok(self
+                .deployer
+                .undeploy_all(&self.definition.settings.work_tree_alias)?)
+        })
+    }
+
+    /// Run deployment action between pre and post hooks.
+    ///
+    /// Trust in hooks is established before anything runs, such that
+    /// untrusted hooks never leave a deployment half done.
+    fn with_hooks(
+        &self,
+        pre: HookKind,
+        post: HookKind,
+        action: impl FnOnce() -> Result<()>,
+    ) -> Result<()> {
+        let settings = &self.definition.settings;
+        let declared = [pre, post]
+            .iter()
+            .any(|kind| !kind.commands(settings).is_empty());
+        if !declared {
+            return action();
+        }
+
+        if !self.hooks.load(Ordering::Relaxed) {
+            debug!("skip {pre} and {post} hooks");
+            return action();
+        }
+
+        self.trust_hooks()?;
+        self.run_hooks(pre)?;
+        action()?;
+        self.run_hooks(post)
+    }
+
+    fn trust_hooks(&self) -> Result<()> {
+        let settings = &self.definition.settings;
+        let trust = self.deployer.get_config(TRUST_KEY)?;
+        if !hooks::is_trusted(settings, trust.as_deref())? {
+            hooks::confirm_trust(&self.name(), settings)?;
+            self.deployer
+                .set_config(TRUST_KEY, &hooks::digest(settings)?)?;
+        }
+
+        Ok(())
+    }
+
+    fn run_hooks(&self, kind: HookKind) -> Result<()> {
+        let name = self.name();
+        let gitdir = self.deployer.gitdir();
+        let work_tree_alias = &self.definition.settings.work_tree_alias;
+        let envs = [
+            ("OXIDOT_CLUSTER", OsStr::new(&name)),
+            ("OXIDOT_GIT_DIR", gitdir.as_os_str()),
+            ("OXIDOT_WORK_TREE", work_tree_alias.as_path().as_os_str()),
+        ];
+        let commands = kind.commands(&self.definition.settings);
+
+        Ok(hooks::run(
+            kind,
+            commands,
+            work_tree_alias.as_path(),
+            &envs,
+        )?)
+    }
+
+    fn name(&self) -> String {
+        // INVARIANT: The name of a cluster is the gitdir name minus the .git extension.
+        self.deployer
+            .gitdir()
+            .file_stem()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
     }
 
     /// List current set of sparsity rules used for deployment.
@@ -229,6 +478,141 @@ pub fn is_deployed(&self) -> bool {
             .is_deployed(&self.definition.settings.work_tree_alias)
     }
 
+    /// Find deployed paths whose mode differs from the mode that cluster
+    /// definition declares for them.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::Deployment`] if deployment logic fails.
+    pub fn permission_drift(&self) -> Result<Vec<Drift>> {
+        Ok(self
+            .deployer
+            .permission_drift(&self.definition.settings.work_tree_alias)?)
+    }
+
+    /// Get source of cluster definition as committed into cluster.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::Deployment`] if deployment logic fails.
+    pub fn definition_source(&self) -> Result<String> {
+        Ok(self.deployer.cat_file("cluster.toml")?)
+    }
+
+    /// Commit new source of cluster definition into cluster.
+    ///
+    /// Source is validated as a cluster definition beforehand. Deployed copy
+    /// of cluster definition is updated as well, unless it has local changes.
+    /// The cluster itself keeps its current definition, so it needs to be
+    /// opened again to pick up the new one.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::Config`] if source is not a valid cluster definition.
+    /// - Return [`Error::Deployment`] if deployment logic fails.
+    /// - Return [`Error::Io`] if deployed copy cannot be updated.
+    pub fn commit_definition(
+        &self,
+        contents: impl AsRef<str>,
+        message: impl AsRef<str>,
+    ) -> Result<()> {
+        contents.as_ref().parse::<ClusterDefinition>()?;
+        let previous = self.definition_source()?;
+        self.deployer
+            .stage_and_commit("cluster.toml", contents.as_ref(), message)?;
+
+        // INVARIANT: Never clobber local changes to deployed cluster definition.
+        let deployed = self
+            .definition
+            .settings
+            .work_tree_alias
+            .as_path()
+            .join("cluster.toml");
+        if std::fs::read_to_string(&deployed).is_ok_and(|current| current == previous) {
+            std::fs::write(&deployed, contents.as_ref())?;
+        }
+
+        Ok(())
+    }
+
+    /// Register filter drivers of cluster, and assign tracked files to them.
+    ///
+    /// Encryption key of cluster store is only generated if `generate_key` is
+    /// set, otherwise a missing key is reported. Writes to the cluster, so the
+    /// cluster store must be locked exclusively.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::Deployment`] if filters cannot be set.
+    pub fn set_filters(&self, generate_key: bool) -> Result<()> {
+        let settings = &self.definition.settings;
+        self.deployer.set_filters(
+            settings.encrypt.iter().flatten(),
+            settings.templates.iter().flatten(),
+            generate_key,
+        )?;
+
+        Ok(())
+    }
+
+    /// Reconcile deployed sparsity rules with default sparsity rules of
+    /// previous cluster settings.
+    ///
+    /// Only applies if cluster is deployed by its previous default sparsity
+    /// rules, which always holds if there were none. Rules that are no longer
+    /// default are undeployed, and new default rules are deployed. Include profiles that match the current machine
+    /// adjust default sparsity rules on both sides.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::Hooks`] if deployment hooks fail, or are not trusted.
+    /// - Return [`Error::Deployment`] if deployment logic fails.
+    pub fn reconcile_default_rules(&self, previous: &ClusterSettings) -> Result<()> {
+        let selection = ProfileSelection::detect();
+        let before = selection.default_rules(previous)?.unwrap_or_default();
+        let after = selection
+            .default_rules(&self.definition.settings)?
+            .unwrap_or_default();
+        let current = self.list_deploy_rules()?;
+        if !before.iter().all(|rule| current.contains(rule)) {
+            debug!("default sparsity rules are not deployed, nothing to reconcile");
+            return Ok(());
+        }
+
+        let removed = before
+            .iter()
+            .filter(|rule| !after.contains(rule))
+            .collect::<Vec<_>>();
+        let added = after
+            .iter()
+            .filter(|rule| !current.contains(rule))
+            .collect::<Vec<_>>();
+        if !removed.is_empty() {
+            info!("undeploy sparsity rules no longer in default set: {removed:?}");
+            self.undeploy_with_rules(removed)?;
+        }
+        if !added.is_empty() {
+            info!("deploy new sparsity rules of default set: {added:?}");
+            self.deploy_with_rules(added)?;
+        }
+
+        Ok(())
+    }
+
+    /// Path to gitdir of cluster.
+    pub fn gitdir(&self) -> &Path {
+        self.deployer.gitdir()
+    }
+
+    /// Set mode that sparse checkout operates in.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::Deployment`] if deployment logic fails.
+    pub fn set_sparse_mode(&self, mode: SparseMode) -> Result<()> {
+        Ok(self.deployer.set_sparse_mode(mode)?)
+    }
+
     /// Interact with cluster directly through Git via current process.
     ///
     /// Preserves consistency between sparsity rules and index when caller
@@ -242,11 +626,67 @@ pub fn gitcall_interactive(
         &self,
         args: impl IntoIterator<Item = impl Into<OsString>>,
     ) -> Result<()> {
+        let args = args.into_iter().map(Into::into).collect::<Vec<OsString>>();
+        let git_args = self.deployer.parse_git_args(&args)?;
+        let command = git_args.command.as_deref().and_then(OsStr::to_str);
+        if command.is_some_and(|command| ADD_COMMANDS.contains(&command)) {
+            self.guard_add(&git_args.args)?;
+        }
+
         Ok(self
             .deployer
             .gitcall_interactive(&self.definition.settings.work_tree_alias, args)?)
     }
 
+    /// Run arbitrary program with cluster as its repository via current
+    /// process.
+    ///
+    /// Program is run with `GIT_DIR` and `GIT_WORK_TREE` exported for the
+    /// cluster. Preserves consistency between sparsity rules and index in the
+    /// same way as [`Cluster::gitcall_interactive`], while holding whatever
+    /// `relock` returns once program exits.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::Deployment`] if deployment logic fails, or `relock`
+    ///   fails.
+    pub fn exec_interactive<G>(
+        &self,
+        program: impl AsRef<OsStr>,
+        args: impl IntoIterator<Item = impl Into<OsString>>,
+        relock: impl FnOnce() -> DeployResult<G>,
+    ) -> Result<()> {
+        Ok(self.deployer.exec_interactive(
+            &self.definition.settings.work_tree_alias,
+            program,
+            args,
+            relock,
+        )?)
+    }
+
+    fn guard_add(&self, args: &[OsString]) -> Result<()> {
+        let settings = &self.definition.settings;
+        let mut deny = settings.deny.clone().unwrap_or_default();
+        match self.deployer.cat_file(IGNORE_FILE) {
+            Ok(content) => deny.extend(content.lines().map(str::to_owned)),
+            Err(DeployError::BlobNotFound { .. }) => (),
+            Err(error) => return Err(error.into()),
+        }
+
+        let guard = AddGuard::new(
+            &settings.work_tree_alias,
+            settings.add_threshold.unwrap_or(DEFAULT_ADD_THRESHOLD),
+            deny,
+        )?;
+        let tracked = self
+            .list_tracked_files()?
+            .into_iter()
+            .collect::<HashSet<_>>();
+        guard.check(args, &tracked)?;
+
+        Ok(())
+    }
+
     /// Interact with cluster directly through Git via external process.
     ///
     /// Does not block current process. Instead the system call is made via
@@ -274,15 +714,39 @@ pub trait ClusterAccess: Send + Sync + 'static {
     /// Open existing cluster.
     fn try_open(path: impl AsRef<Path>) -> Result<Cluster>;
 
-    /// Clone existing cluster from remote repository.
+    /// Clone existing cluster from first of remote URLs that works.
     fn try_clone(
-        url: impl AsRef<str>,
+        urls: impl IntoIterator<Item = impl AsRef<str>>,
         path: impl AsRef<Path>,
         branch: BranchTarget,
+        url_rewrites: &UrlRewrites,
         bar: ProgressBar,
     ) -> Result<Cluster>;
 }
 
+/// Deployment state of named group of sparsity rules.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum GroupState {
+    /// Every rule of group is in current sparsity rules.
+    Deployed,
+
+    /// Only some rules of group are in current sparsity rules.
+    Partial,
+
+    /// No rule of group is in current sparsity rules.
+    Undeployed,
+}
+
+impl Display for GroupState {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Self::Deployed => fmt.pad("deployed"),
+            Self::Partial => fmt.pad("partial"),
+            Self::Undeployed => fmt.pad("undeployed"),
+        }
+    }
+}
+
 #[derive(Default, Clone, PartialEq, Eq, Hash, Debug)]
 pub enum BranchTarget {
     #[default]
@@ -312,12 +776,15 @@ fn try_init(path: impl AsRef<Path>, definition: ClusterDefinition) -> Result<Clu
         let repository = Repository::init_bare(path.as_ref())?;
         let matcher = InvertedGitignore::new();
         let sparsity = SparsityDrafter::new(path.as_ref(), matcher)?;
-        let deployer = Git2Deployer::new(repository, sparsity)?;
+        let mut deployer = Git2Deployer::new(repository, sparsity)?;
+        configure_deployer(&mut deployer, &definition)?;
 
-        let cluster = Cluster {
-            definition,
-            deployer,
-        };
+        // INVARIANT: Hooks of clusters initialized locally come from the user.
+        deployer.set_config(TRUST_KEY, &hooks::digest(&definition.settings)?)?;
+
+        // INVARIANT: Encryption key is only generated by explicit local setup.
+        let cluster = Cluster::new(definition, deployer);
+        cluster.set_filters(true)?;
         let contents = &cluster.definition.to_string();
         info!(
             "stage and commit the following cluster definition:\n{}",
@@ -348,21 +815,23 @@ fn try_open(path: impl AsRef<Path>) -> Result<Cluster> {
         let repository = Repository::open(path.as_ref())?;
         let matcher = InvertedGitignore::new();
         let sparsity = SparsityDrafter::new(path.as_ref(), matcher)?;
-        let deployer = Git2Deployer::new(repository, sparsity)?;
-        let definition = deployer.cat_file("cluster.toml")?.parse()?;
+        let mut deployer = Git2Deployer::new(repository, sparsity)?;
+        let definition: ClusterDefinition = deployer.cat_file("cluster.toml")?.parse()?;
+        configure_deployer(&mut deployer, &definition)?;
 
-        Ok(Cluster {
-            definition,
-            deployer,
-        })
+        Ok(Cluster::new(definition, deployer))
     }
 
     /// Clone existing cluster from remote repository.
     ///
-    /// Clones existing cluster from remote repository through target URL to
-    /// target path. The progress of the clone is displayed through a progress
-    /// bar. The cluster definition is then extracted at the top-level after
-    /// the cloning is done.
+    /// Clones existing cluster from remote repository through the first of
+    /// target URLs to target path. If cloning fails, then each following URL
+    /// is tried in order as a mirror of the first. Either way, the origin
+    /// remote of the cluster points at the first URL afterwards. The progress
+    /// of the clone is displayed through a progress bar. The cluster
+    /// definition is then extracted at the top-level after the cloning is
+    /// done, and any extra remotes that it names are added to the cluster
+    /// with URL rewrite rules applied.
     ///
     /// If any credentials are required for the clone to continue is required,
     /// then the user will be prompted for that information accordingly. The
@@ -370,13 +839,16 @@ fn try_open(path: impl AsRef<Path>) -> Result<Cluster> {
     ///
     /// # Errors
     ///
-    /// - Return [`Error::Git2`] if libgit2 operations fail.
+    /// - Return [`Error::NoRemoteUrl`] if no URL is given.
+    /// - Return [`Error::Git2`] if libgit2 operations fail, with the error of
+    ///   the last URL tried.
     /// - Return [`Error::Config`] if cluster definition parsing fails.
     /// - Return [`Error::Deployment`] if deployment logic fails.
     fn try_clone(
-        url: impl AsRef<str>,
+        urls: impl IntoIterator<Item = impl AsRef<str>>,
         path: impl AsRef<Path>,
         branch: BranchTarget,
+        url_rewrites: &UrlRewrites,
         bar: ProgressBar,
     ) -> Result<Cluster> {
         let style = ProgressStyle::with_template(
@@ -384,50 +856,112 @@ fn try_clone(
         )?
         .progress_chars("-Cco.");
         bar.set_style(style);
-        bar.set_message(url.as_ref().to_string());
         bar.enable_steady_tick(std::time::Duration::from_millis(100));
 
-        let prompter = IndicatifPrompter::new(bar);
-        let authenticator = GitAuthenticator::default().set_prompter(prompter.clone());
-        let config = Config::open_default()?;
-
-        let mut throttle = time::Instant::now();
-        let mut rc = RemoteCallbacks::new();
-        rc.credentials(authenticator.credentials(&config));
-        rc.transfer_progress(|progress| {
-            let stats = progress.to_owned();
-            let bar_size = stats.total_objects() as u64;
-            let bar_pos = stats.received_objects() as u64;
-            if throttle.elapsed() > time::Duration::from_millis(10) {
-                throttle = time::Instant::now();
-                prompter.bar.set_length(bar_size);
-                prompter.bar.set_position(bar_pos);
+        // INVARIANT: Never remove a path that existed before cloning.
+        let existed = path.as_ref().exists();
+        let mut urls = urls.into_iter();
+        let mut url = urls.next().ok_or(Error::NoRemoteUrl)?;
+        let primary = url.as_ref().to_string();
+        let repository = loop {
+            match clone_bare(url.as_ref(), path.as_ref(), &branch, &bar) {
+                Ok(repository) => break repository,
+                Err(error) => {
+                    let Some(mirror) = urls.next() else {
+                        return Err(error);
+                    };
+                    warn!(
+                        "cannot clone from {:?}: {error}, falling back to {:?}",
+                        url.as_ref(),
+                        mirror.as_ref()
+                    );
+                    if !existed && path.as_ref().exists() {
+                        std::fs::remove_dir_all(path.as_ref())?;
+                    }
+                    url = mirror;
+                }
             }
-            true
-        });
-
-        let mut fo = FetchOptions::new();
-        fo.remote_callbacks(rc);
-        let mut builder = RepoBuilder::new();
-        let builder = if let BranchTarget::Target(name) = branch {
-            builder.bare(true).branch(name.as_str()).fetch_options(fo)
-        } else {
-            builder.bare(true).fetch_options(fo)
         };
 
-        let repository = builder.clone(url.as_ref(), path.as_ref())?;
+        // INVARIANT: Mirrors are only fallbacks, origin stays the primary URL.
+        if url.as_ref() != primary {
+            info!(
+                "point origin at {primary:?} instead of mirror {:?}",
+                url.as_ref()
+            );
+            repository.remote_set_url("origin", &primary)?;
+        }
+
         let matcher = InvertedGitignore::new();
         let sparsity = SparsityDrafter::new(path.as_ref(), matcher)?;
-        let deployer = Git2Deployer::new(repository, sparsity)?;
-        let definition = deployer.cat_file("cluster.toml")?.parse()?;
+        let mut deployer = Git2Deployer::new(repository, sparsity)?;
+        let definition: ClusterDefinition = deployer.cat_file("cluster.toml")?.parse()?;
+        configure_deployer(&mut deployer, &definition)?;
+        deployer.add_remotes(definition.settings.remotes.iter().flatten(), url_rewrites)?;
 
-        Ok(Cluster {
-            definition,
-            deployer,
-        })
+        let cluster = Cluster::new(definition, deployer);
+        cluster.set_filters(false)?;
+
+        Ok(cluster)
     }
 }
 
+/// Clone bare repository from URL, showing progress on progress bar.
+fn clone_bare(
+    url: &str,
+    path: &Path,
+    branch: &BranchTarget,
+    bar: &ProgressBar,
+) -> Result<Repository> {
+    bar.set_message(url.to_string());
+    bar.set_position(0);
+
+    let prompter = IndicatifPrompter::new(bar.clone());
+    let authenticator = GitAuthenticator::default().set_prompter(prompter.clone());
+    let config = Config::open_default()?;
+
+    let mut throttle = time::Instant::now();
+    let mut rc = RemoteCallbacks::new();
+    rc.credentials(authenticator.credentials(&config));
+    rc.transfer_progress(|progress| {
+        let stats = progress.to_owned();
+        let bar_size = stats.total_objects() as u64;
+        let bar_pos = stats.received_objects() as u64;
+        if throttle.elapsed() > time::Duration::from_millis(10) {
+            throttle = time::Instant::now();
+            prompter.bar.set_length(bar_size);
+            prompter.bar.set_position(bar_pos);
+        }
+        true
+    });
+
+    let mut fo = FetchOptions::new();
+    fo.remote_callbacks(rc);
+    let mut builder = RepoBuilder::new();
+    let builder = if let BranchTarget::Target(name) = branch {
+        builder.bare(true).branch(name.as_str()).fetch_options(fo)
+    } else {
+        builder.bare(true).fetch_options(fo)
+    };
+
+    Ok(builder.clone(url, path)?)
+}
+
+/// Apply settings of cluster definition that deployer is responsible for.
+///
+/// Only configures the deployer itself, nothing is written to the cluster.
+/// See [`Cluster::set_filters`] for settings that need to be written.
+fn configure_deployer(deployer: &mut Git2Deployer, definition: &ClusterDefinition) -> Result<()> {
+    let settings = &definition.settings;
+
+    // INVARIANT: Encrypted paths never reach the cluster in plain text.
+    let encrypt = settings.encrypt.iter().flatten();
+    deployer.allow_secrets(settings.allow_secrets.iter().flatten().chain(encrypt))?;
+    deployer.set_permissions(settings.permissions.iter().flatten())?;
+
+    Ok(())
+}
+
 /// Git2 authentication prompter for progress bar.
 #[derive(Debug, Clone)]
 pub struct IndicatifPrompter {
@@ -506,6 +1040,22 @@ pub enum Error {
     #[error(transparent)]
     Sparse(#[from] crate::cluster::sparse::Error),
 
+    /// Cluster definition has no group with target name.
+    #[error("cluster has no group named {name:?}")]
+    GroupNotFound { name: String },
+
+    /// Deployment hooks fail, or are not trusted.
+    #[error(transparent)]
+    Hooks(#[from] crate::cluster::hooks::Error),
+
+    /// Include profiles cannot be selected.
+    #[error(transparent)]
+    Profile(#[from] crate::cluster::profile::Error),
+
+    /// Git-add guard refuses to stage files.
+    #[error(transparent)]
+    Guard(#[from] crate::cluster::guard::Error),
+
     /// Cluster definition parsing fails.
     #[error(transparent)]
     Config(#[from] crate::config::Error),
@@ -517,7 +1067,182 @@ pub enum Error {
     /// Operations from libgit2 fail.
     #[error(transparent)]
     Git2(#[from] git2::Error),
+
+    /// No remote URL to clone cluster from.
+    #[error("no remote URL to clone cluster from")]
+    NoRemoteUrl,
+
+    /// Input/Output operations failed.
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
 }
 
 /// Friendly result alias :3
 pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::edit::DefinitionEditor;
+    use pretty_assertions::assert_eq;
+    use sealed_test::prelude::*;
+
+    fn init_cluster(settings: &str) -> anyhow::Result<Cluster> {
+        // INVARIANT: Commits need an identity that does not depend on the host.
+        let mut config = Repository::init_bare("foo.git")?.config()?;
+        config.set_str("user.name", "blah")?;
+        config.set_str("user.email", "blah@blah.org")?;
+
+        std::fs::create_dir("home")?;
+        let home = std::env::current_dir()?.join("home");
+        let definition = format!(
+            "[settings]\ndescription = \"foo\"\nwork_tree_alias = {:?}\n{settings}\n\
+             [settings.remote]\nurl = \"https://blah.org/foo.git\"\n",
+            home.display()
+        );
+
+        Ok(Git2Cluster::try_init("foo.git", definition.parse()?)?)
+    }
+
+    #[sealed_test]
+    fn undeploy_group_keeps_shared_rules() -> anyhow::Result<()> {
+        let cluster = init_cluster(
+            r#"include = ["/.bashrc"]
+
+            [settings.groups]
+            vim = ["/.vimrc", "/.editorconfig"]
+            emacs = ["/.emacs", "/.editorconfig", "/.bashrc"]
+            "#,
+        )?;
+        cluster.deploy_default_rules()?;
+        cluster.deploy_group("vim")?;
+        cluster.deploy_group("emacs")?;
+        let expect = vec![
+            ("emacs".to_string(), GroupState::Deployed),
+            ("vim".into(), GroupState::Deployed),
+        ];
+        assert_eq!(cluster.group_status()?, expect);
+
+        // Rule of other deployed group stays.
+        cluster.undeploy_group("vim")?;
+        let mut result = cluster.list_deploy_rules()?;
+        result.sort();
+        assert_eq!(result, vec!["/.bashrc", "/.editorconfig", "/.emacs"]);
+        let expect = vec![
+            ("emacs".to_string(), GroupState::Deployed),
+            ("vim".into(), GroupState::Partial),
+        ];
+        assert_eq!(cluster.group_status()?, expect);
+
+        // Rule of deployed default rules stays.
+        cluster.undeploy_group("emacs")?;
+        assert_eq!(cluster.list_deploy_rules()?, vec!["/.bashrc"]);
+        let expect = vec![
+            ("emacs".to_string(), GroupState::Partial),
+            ("vim".into(), GroupState::Undeployed),
+        ];
+        assert_eq!(cluster.group_status()?, expect);
+
+        let result = cluster.deploy_group("nope");
+        assert!(matches!(result, Err(Error::GroupNotFound { name }) if name == "nope"));
+
+        Ok(())
+    }
+
+    #[sealed_test]
+    fn reconcile_default_rules_follows_new_defaults() -> anyhow::Result<()> {
+        let cluster = init_cluster(r#"include = ["/.bashrc", "/.profile"]"#)?;
+        let previous = cluster.definition.settings.clone();
+        cluster.deploy_default_rules()?;
+        cluster.deploy_with_rules(["/.extra"])?;
+
+        let mut editor: DefinitionEditor = cluster.definition_source()?.parse()?;
+        editor.set("include", r#"["/.profile", "/.vimrc"]"#)?;
+        cluster.commit_definition(editor.to_string(), "chore: edit cluster.toml")?;
+        let cluster = Git2Cluster::try_open("foo.git")?;
+        cluster.reconcile_default_rules(&previous)?;
+        let mut result = cluster.list_deploy_rules()?;
+        result.sort();
+        assert_eq!(result, vec!["/.extra", "/.profile", "/.vimrc"]);
+
+        // Nothing is reconciled once default rules are no longer deployed.
+        cluster.undeploy_with_rules(["/.vimrc"])?;
+        let previous = cluster.definition.settings.clone();
+        editor.set("include", r#"["/.bashrc"]"#)?;
+        cluster.commit_definition(editor.to_string(), "chore: edit cluster.toml")?;
+        let cluster = Git2Cluster::try_open("foo.git")?;
+        cluster.reconcile_default_rules(&previous)?;
+        let mut result = cluster.list_deploy_rules()?;
+        result.sort();
+        assert_eq!(result, vec!["/.extra", "/.profile"]);
+
+        // First default rules are deployed once include is set.
+        editor.unset("include")?;
+        cluster.commit_definition(editor.to_string(), "chore: edit cluster.toml")?;
+        let cluster = Git2Cluster::try_open("foo.git")?;
+        let previous = cluster.definition.settings.clone();
+        editor.set("include", r#"["/.vimrc"]"#)?;
+        cluster.commit_definition(editor.to_string(), "chore: edit cluster.toml")?;
+        let cluster = Git2Cluster::try_open("foo.git")?;
+        cluster.reconcile_default_rules(&previous)?;
+        let mut result = cluster.list_deploy_rules()?;
+        result.sort();
+        assert_eq!(result, vec!["/.extra", "/.profile", "/.vimrc"]);
+
+        Ok(())
+    }
+
+    #[sealed_test]
+    fn try_clone_falls_back_to_mirrors() -> anyhow::Result<()> {
+        init_cluster("")?;
+        let primary = std::env::current_dir()?.join("nope.git");
+        let mirror = std::env::current_dir()?.join("foo.git");
+        let urls = [primary.to_string_lossy(), mirror.to_string_lossy()];
+
+        let cluster = Git2Cluster::try_clone(
+            urls,
+            "bar.git",
+            BranchTarget::Default,
+            &UrlRewrites::default(),
+            ProgressBar::hidden(),
+        )?;
+        assert_eq!(cluster.definition.settings.description, "foo");
+        let repository = Repository::open_bare("bar.git")?;
+        let origin = repository.find_remote("origin")?;
+        assert_eq!(origin.url(), Some(primary.to_string_lossy().as_ref()));
+
+        // Partial clones are removed, but never a path that existed before.
+        let urls = [primary.to_string_lossy(), primary.to_string_lossy()];
+        let result = Git2Cluster::try_clone(
+            urls.clone(),
+            "baz.git",
+            BranchTarget::Default,
+            &UrlRewrites::default(),
+            ProgressBar::hidden(),
+        );
+        assert!(matches!(result, Err(Error::Git2(_))));
+        assert!(!Path::new("baz.git").exists());
+
+        std::fs::create_dir("baz.git")?;
+        let result = Git2Cluster::try_clone(
+            urls,
+            "baz.git",
+            BranchTarget::Default,
+            &UrlRewrites::default(),
+            ProgressBar::hidden(),
+        );
+        assert!(matches!(result, Err(Error::Git2(_))));
+        assert!(Path::new("baz.git").exists());
+
+        let result = Git2Cluster::try_clone(
+            Vec::<String>::new(),
+            "baz.git",
+            BranchTarget::Default,
+            &UrlRewrites::default(),
+            ProgressBar::hidden(),
+        );
+        assert!(matches!(result, Err(Error::NoRemoteUrl)));
+
+        Ok(())
+    }
+}
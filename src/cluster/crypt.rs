@@ -0,0 +1,345 @@
+// SPDX-FileCopyrightText: 2025 Jason Pena <jasonpena@awkless.com>
+// SPDX-License-Identifier: MIT
+
+//! Encryption of tracked files.
+//!
+//! Some dotfiles cannot be tracked in plain text at all, e.g., `.netrc`,
+//! `~/.ssh/config`, files full of API tokens, etc. Thus, Oxidot can encrypt
+//! the content of these files whenever they are committed into a cluster, and
+//! decrypt them back into the work tree alias whenever they are deployed.
+//!
+//! # Encrypted Paths
+//!
+//! Paths to encrypt are listed in the `encrypt` setting of the cluster
+//! definition. Entries use gitattributes syntax relative to the top-level of
+//! the cluster, e.g., `.ssh/config` or `.secrets/**`. The cluster definition
+//! itself is never encrypted.
+//!
+//! # Filter Driver
+//!
+//! Encryption hooks into Git as a clean and smudge filter driver, such that
+//! plain Git commands on a cluster encrypt and decrypt transparently. The
+//! same driver is also registered as a textconv diff driver, such that diffs
+//! show plain text. See [`crate::cluster::filter`] for details.
+//!
+//! # Keys
+//!
+//! Content is encrypted with [age] using an X25519 key that is stored locally
+//! at the top-level of the cluster store in a file named `oxidot.key`. The key
+//! is only generated by local setup, i.e., when a cluster that encrypts files
+//! is initialized, or when encryption is first set in a cluster definition. It
+//! is shared by every cluster in the cluster store. The key is never committed,
+//! so it must be copied over manually to deploy encrypted files on another
+//! machine. Opening or cloning a cluster that encrypts files without the key
+//! only reports it missing.
+//!
+//! [age]: https://age-encryption.org
+
+use crate::cluster::filter::staged_content;
+
+use age::{
+    secrecy::ExposeSecret,
+    x25519::{Identity, Recipient},
+};
+use std::{
+    fmt::{Debug, Formatter, Result as FmtResult},
+    fs::{create_dir_all, read, read_to_string, OpenOptions},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+use tracing::{info, instrument};
+
+/// Name of key file at top-level of cluster store.
+pub const KEY_FILE: &str = "oxidot.key";
+
+/// Magic that every age encrypted file starts with.
+const AGE_MAGIC: &[u8] = b"age-encryption.org/";
+
+/// Determine path to key file of cluster store that contains gitdir.
+pub fn key_file(gitdir: impl AsRef<Path>) -> PathBuf {
+    let gitdir = gitdir.as_ref();
+    gitdir.parent().unwrap_or(gitdir).join(KEY_FILE)
+}
+
+/// Check if content is already encrypted.
+pub fn is_encrypted(content: &[u8]) -> bool {
+    content.starts_with(AGE_MAGIC)
+}
+
+/// Encrypt and decrypt content with locally stored key.
+pub struct Crypt {
+    identity: Identity,
+}
+
+impl Debug for Crypt {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> FmtResult {
+        // INVARIANT: Never leak the secret key through debug output.
+        fmt.debug_struct("Crypt")
+            .field("recipient", &self.recipient().to_string())
+            .finish()
+    }
+}
+
+impl Crypt {
+    /// Load key from key file.
+    ///
+    /// Key file uses the same format as age-keygen, i.e., comment lines
+    /// starting with "#" followed by the secret key itself.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::KeyNotFound`] if key file does not exist.
+    /// - Return [`Error::InvalidKey`] if key file does not contain a key.
+    /// - Return [`Error::Io`] if key file cannot be read.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let data = match read_to_string(path) {
+            Ok(data) => data,
+            Err(error) if error.kind() == ErrorKind::NotFound => {
+                return Err(Error::KeyNotFound { path: path.into() })
+            }
+            Err(error) => return Err(error.into()),
+        };
+
+        let identity = data
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty() && !line.starts_with('#'))
+            .and_then(|line| line.parse::<Identity>().ok())
+            .ok_or_else(|| Error::InvalidKey { path: path.into() })?;
+
+        Ok(Self { identity })
+    }
+
+    /// Load key from key file, or generate it if it does not exist yet.
+    ///
+    /// Generated key files are only readable by their owner.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::InvalidKey`] if existing key file does not contain a
+    ///   key.
+    /// - Return [`Error::Io`] if key file cannot be read or written.
+    #[instrument(skip(path), level = "debug")]
+    pub fn load_or_generate(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        match Self::load(path) {
+            Err(Error::KeyNotFound { .. }) => (),
+            result => return result,
+        }
+
+        info!("generate new encryption key at {:?}", path.display());
+        let identity = Identity::generate();
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+
+        let mut file = options.open(path)?;
+        writeln!(file, "# public key: {}", identity.to_public())?;
+        writeln!(file, "{}", identity.to_string().expose_secret())?;
+
+        Ok(Self { identity })
+    }
+
+    /// Public key that content is encrypted to.
+    pub fn recipient(&self) -> Recipient {
+        self.identity.to_public()
+    }
+
+    /// Encrypt plain text.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::Encrypt`] if content cannot be encrypted.
+    pub fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        Ok(age::encrypt(&self.recipient(), plaintext)?)
+    }
+
+    /// Decrypt cipher text.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::Decrypt`] if content cannot be decrypted with key.
+    pub fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>> {
+        Ok(age::decrypt(&self.identity, ciphertext)?)
+    }
+
+    /// Encrypt plain text for storage in cluster.
+    ///
+    /// Age never produces the same cipher text twice, so Git would consider
+    /// encrypted files modified after every clean. Thus, `previous` cipher
+    /// text is reused as is if it decrypts to the same plain text. Content
+    /// that is already encrypted is left alone.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::Encrypt`] if content cannot be encrypted.
+    pub fn clean(&self, plaintext: &[u8], previous: Option<&[u8]>) -> Result<Vec<u8>> {
+        if is_encrypted(plaintext) {
+            return Ok(plaintext.to_vec());
+        }
+
+        if let Some(previous) = previous.filter(|previous| is_encrypted(previous)) {
+            if self
+                .decrypt(previous)
+                .is_ok_and(|decrypted| decrypted == plaintext)
+            {
+                return Ok(previous.to_vec());
+            }
+        }
+
+        self.encrypt(plaintext)
+    }
+
+    /// Decrypt content from cluster for deployment.
+    ///
+    /// Content that is not encrypted is left alone, e.g., files that were
+    /// committed before they were marked for encryption.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::Decrypt`] if content cannot be decrypted with key.
+    pub fn smudge(&self, content: &[u8]) -> Result<Vec<u8>> {
+        if !is_encrypted(content) {
+            return Ok(content.to_vec());
+        }
+
+        self.decrypt(content)
+    }
+}
+
+/// Clean and smudge filter driver for a cluster.
+///
+/// Invoked by Git through `oxidot filter` for every encrypted path.
+#[derive(Debug)]
+pub struct CryptFilter {
+    gitdir: PathBuf,
+}
+
+impl CryptFilter {
+    /// Construct new filter driver for cluster at gitdir.
+    pub fn new(gitdir: impl Into<PathBuf>) -> Self {
+        Self {
+            gitdir: gitdir.into(),
+        }
+    }
+
+    /// Encrypt plain text of tracked file at path.
+    ///
+    /// Path is relative to top-level of cluster. Cipher text currently staged
+    /// for that path is reused if its plain text did not change.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::Git2`] if staged content cannot be looked up.
+    /// - Return [`Error::KeyNotFound`] or [`Error::InvalidKey`] if key cannot
+    ///   be loaded.
+    /// - Return [`Error::Encrypt`] if content cannot be encrypted.
+    pub fn clean(&self, path: impl AsRef<Path>, plaintext: &[u8]) -> Result<Vec<u8>> {
+        let crypt = Crypt::load(key_file(&self.gitdir))?;
+        let previous = staged_content(&self.gitdir, path)?;
+        crypt.clean(plaintext, previous.as_deref())
+    }
+
+    /// Decrypt content of tracked file.
+    ///
+    /// The key is only loaded if content is actually encrypted.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::KeyNotFound`] or [`Error::InvalidKey`] if key cannot
+    ///   be loaded.
+    /// - Return [`Error::Decrypt`] if content cannot be decrypted with key.
+    pub fn smudge(&self, content: &[u8]) -> Result<Vec<u8>> {
+        if !is_encrypted(content) {
+            return Ok(content.to_vec());
+        }
+
+        Crypt::load(key_file(&self.gitdir))?.smudge(content)
+    }
+
+    /// Decrypt content of file at path for display in diffs.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::Io`] if file cannot be read.
+    /// - Return [`Error::KeyNotFound`] or [`Error::InvalidKey`] if key cannot
+    ///   be loaded.
+    /// - Return [`Error::Decrypt`] if content cannot be decrypted with key.
+    pub fn textconv(&self, path: impl AsRef<Path>) -> Result<Vec<u8>> {
+        self.smudge(&read(path)?)
+    }
+}
+
+/// Encryption error types.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Key file does not exist.
+    #[error(
+        "cannot find encryption key at {:?}, copy it from a machine that has it",
+        path.display()
+    )]
+    KeyNotFound { path: PathBuf },
+
+    /// Key file does not contain a valid key.
+    #[error("invalid encryption key at {:?}", path.display())]
+    InvalidKey { path: PathBuf },
+
+    /// Content cannot be encrypted.
+    #[error(transparent)]
+    Encrypt(#[from] age::EncryptError),
+
+    /// Content cannot be decrypted.
+    #[error(transparent)]
+    Decrypt(#[from] age::DecryptError),
+
+    /// Operations from libgit2 fail.
+    #[error(transparent)]
+    Git2(#[from] git2::Error),
+
+    /// Key file or content cannot be read or written.
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// Friendly result alias :3
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use sealed_test::prelude::*;
+
+    #[sealed_test]
+    fn crypt_clean_smudge_roundtrip() -> anyhow::Result<()> {
+        let path = Path::new("store").join(KEY_FILE);
+        let crypt = Crypt::load_or_generate(&path)?;
+        let plaintext = b"machine example.org password hunter2\n";
+
+        let ciphertext = crypt.clean(plaintext, None)?;
+        assert!(is_encrypted(&ciphertext));
+        assert_eq!(crypt.smudge(&ciphertext)?, plaintext.to_vec());
+
+        // Unchanged content reuses previous cipher text.
+        assert_eq!(crypt.clean(plaintext, Some(&ciphertext))?, ciphertext);
+        assert_ne!(crypt.clean(b"changed\n", Some(&ciphertext))?, ciphertext);
+
+        // Plain content from before encryption passes through.
+        assert_eq!(crypt.smudge(b"plain\n")?, b"plain\n".to_vec());
+
+        // Existing key is loaded instead of generating a new one.
+        let reloaded = Crypt::load_or_generate(&path)?;
+        assert_eq!(reloaded.smudge(&ciphertext)?, plaintext.to_vec());
+
+        Ok(())
+    }
+}
@@ -12,15 +12,24 @@
 //! considered to be deployment logic.
 
 use crate::{
-    cluster::sparse::{InvertedGitignore, SparsityDrafter},
-    config::WorkTreeAlias,
+    cluster::{
+        crypt::{key_file, Crypt},
+        filter::{render_attributes, replace_attributes, FilterDriver},
+        passthrough::{CommitOptions, GitArgs},
+        permissions::{deployed_files, Drift, Permissions},
+        secrets::{Error as SecretsError, SecretScanner},
+        sparse::{InvertedGitignore, SparsityDrafter},
+    },
+    config::{SparseMode, UrlRewrites, WorkTreeAlias},
 };
 
-use git2::{Blob, IndexEntry, IndexTime, ObjectType, Repository};
+use git2::{Blob, Delta, IndexEntry, IndexTime, ObjectType, Oid, Repository};
 use std::{
-    collections::{HashSet, VecDeque},
+    collections::{HashMap, VecDeque},
     ffi::{OsStr, OsString},
     fmt::{Debug, Formatter, Result as FmtResult},
+    fs::{create_dir_all, read, read_to_string, write},
+    io::ErrorKind,
     path::{Path, PathBuf},
     process::Command,
 };
@@ -71,6 +80,9 @@ fn undeploy_with_rules(
     /// Check if cluster has deployed any tracked files to work tree alias.
     fn is_deployed(&self, work_tree_alias: &WorkTreeAlias) -> bool;
 
+    /// Set mode that sparse checkout operates in.
+    fn set_sparse_mode(&self, mode: SparseMode) -> Result<()>;
+
     /// Block process to intract with cluster through Git.
     fn gitcall_interactive(
         &self,
@@ -84,12 +96,54 @@ fn gitcall_non_interactive(
         work_tree_alias: &WorkTreeAlias,
         args: impl IntoIterator<Item = impl Into<OsString>>,
     ) -> Result<String>;
+
+    /// Parse Git passthrough arguments with aliases expanded.
+    fn parse_git_args(&self, args: &[OsString]) -> Result<GitArgs>;
+
+    /// Block process to run arbitrary program with cluster as its repository.
+    fn exec_interactive<G>(
+        &self,
+        work_tree_alias: &WorkTreeAlias,
+        program: impl AsRef<OsStr>,
+        args: impl IntoIterator<Item = impl Into<OsString>>,
+        relock: impl FnOnce() -> Result<G>,
+    ) -> Result<()>;
+
+    /// Path to gitdir of cluster.
+    fn gitdir(&self) -> &Path;
+
+    /// Get value of configuration setting of cluster.
+    fn get_config(&self, key: &str) -> Result<Option<String>>;
+
+    /// Set value of configuration setting of cluster.
+    fn set_config(&self, key: &str, value: &str) -> Result<()>;
+
+    /// Find deployed paths whose mode differs from their declared mode.
+    fn permission_drift(&self, work_tree_alias: &WorkTreeAlias) -> Result<Vec<Drift>>;
+
+    /// Assign tracked files to filter drivers that Oxidot implements.
+    fn set_filters(
+        &self,
+        encrypt: impl IntoIterator<Item = impl AsRef<str>>,
+        templates: impl IntoIterator<Item = impl AsRef<str>>,
+        generate_key: bool,
+    ) -> Result<()>;
 }
 
+/// Git configuration settings that every cluster must have for deployment.
+///
+/// Enforced by [`Git2Deployer::new`], and checked by [`crate::doctor`].
+pub const REQUIRED_CONFIG: [(&str, &str); 2] = [
+    ("status.showUntrackedFiles", "no"),
+    ("core.sparseCheckout", "true"),
+];
+
 /// Cluster deployment logic backed by libgit2.
 pub struct Git2Deployer {
     repository: Repository,
     sparsity: SparsityDrafter<InvertedGitignore>,
+    secrets: SecretScanner,
+    permissions: Permissions,
 }
 
 impl Git2Deployer {
@@ -113,17 +167,16 @@ pub fn new(
         let deployer = Self {
             repository,
             sparsity,
+            secrets: SecretScanner::default(),
+            permissions: Permissions::default(),
         };
 
-        // INVARIANT: Do not show untracked files.
+        // INVARIANT: Do not show untracked files, and always enable sparse checkout.
         let mut config = deployer.repository.config()?;
-        if deployer.get_config_value(&config, "status.showUntrackedFiles")? != Some("no".into()) {
-            config.set_str("status.showUntrackedFiles", "no")?;
-        }
-
-        // INVARIANT: Always enable sparse checkout.
-        if deployer.get_config_value(&config, "core.sparseCheckout")? != Some("true".into()) {
-            config.set_str("core.sparseCheckout", "true")?;
+        for (key, value) in REQUIRED_CONFIG {
+            if deployer.get_config_value(&config, key)?.as_deref() != Some(value) {
+                config.set_str(key, value)?;
+            }
         }
 
         // INVARIANT: Allow changes to work tree alias outside of sparsity rules.
@@ -134,6 +187,81 @@ pub fn new(
         Ok(deployer)
     }
 
+    /// Exclude paths from secret scanning before commits.
+    ///
+    /// Rules use gitignore syntax relative to the top-level of the cluster,
+    /// and replace any previously allowed paths.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::Secrets`] if any rule is invalid.
+    pub fn allow_secrets(&mut self, rules: impl IntoIterator<Item = impl AsRef<str>>) -> Result<()> {
+        self.secrets = SecretScanner::new(rules)?;
+        Ok(())
+    }
+
+    /// Declare modes that deployed paths must have.
+    ///
+    /// Patterns map to octal modes, and replace any previously declared
+    /// modes. Modes are applied after every checkout.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::Permissions`] if any pattern or mode is invalid.
+    pub fn set_permissions(
+        &mut self,
+        entries: impl IntoIterator<Item = (impl AsRef<str>, impl AsRef<str>)>,
+    ) -> Result<()> {
+        self.permissions = Permissions::new(entries)?;
+        Ok(())
+    }
+
+    /// Add named remotes to cluster.
+    ///
+    /// URLs of remotes are rewritten by URL rewrite rules first. Remotes that
+    /// already exist are left alone, even if their URL differs, because the
+    /// user may have changed them on purpose.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::Git2`] if any remote cannot be created.
+    #[instrument(skip_all, level = "debug")]
+    pub fn add_remotes(
+        &self,
+        remotes: impl IntoIterator<Item = (impl AsRef<str>, impl AsRef<str>)>,
+        url_rewrites: &UrlRewrites,
+    ) -> Result<()> {
+        for (name, url) in remotes {
+            let (name, url) = (name.as_ref(), url_rewrites.apply(url.as_ref()));
+            let url = url.as_str();
+            match self.repository.find_remote(name) {
+                Ok(existing) if existing.url() != Some(url) => {
+                    warn!("keep existing remote {name:?} instead of {url:?}");
+                }
+                Ok(_) => (),
+                Err(_) => {
+                    info!("add remote {name:?} at {url:?}");
+                    self.repository.remote(name, url)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Apply sparsity rules to work tree alias, and enforce declared modes on
+    /// whatever got deployed.
+    fn checkout(&self, work_tree_alias: &WorkTreeAlias) -> Result<String> {
+        let output =
+            syscall_non_interactive("git", self.expand_bin_args(work_tree_alias, ["checkout"])?)?;
+        if !self.permissions.is_empty() {
+            let files = deployed_files(&self.repository, work_tree_alias.as_path())?;
+            self.permissions.apply(work_tree_alias.as_path(), &files)?;
+        }
+
+        Ok(output)
+    }
+
     fn get_config_value(&self, config: &git2::Config, key: &str) -> Result<Option<String>> {
         match config.get_entry(key) {
             Ok(entry) => Ok(entry.value().map(|v| v.to_string())),
@@ -212,7 +340,7 @@ fn expand_bin_args(
         &self,
         work_tree_alias: &WorkTreeAlias,
         args: impl IntoIterator<Item = impl Into<OsString>>,
-    ) -> Vec<OsString> {
+    ) -> Result<Vec<OsString>> {
         let gitdir = self.repository.path().to_string_lossy().into_owned().into();
         let path_args: Vec<OsString> = vec![
             "--git-dir".into(),
@@ -221,38 +349,197 @@ fn expand_bin_args(
             work_tree_alias.to_os_string(),
         ];
 
-        let mut user_args = args.into_iter().map(Into::into).collect::<Vec<_>>();
-        if self.should_add_sparse_flag(&user_args) {
-            user_args.splice(1..1, ["--sparse".into()]);
+        // INVARIANT: Only replace aliases with their expansion when sparse flag is needed.
+        let args = args.into_iter().map(Into::into).collect::<Vec<OsString>>();
+        let mut user_args = GitArgs::parse(args.clone());
+        let mut expanded = self.parse_git_args(&args)?;
+        if expanded.insert_sparse_flag() {
+            user_args = expanded;
         }
 
         let mut bin_args: Vec<OsString> = Vec::new();
         bin_args.extend(path_args);
-        bin_args.extend(user_args);
+        bin_args.extend(user_args.into_args());
 
-        bin_args
+        Ok(bin_args)
     }
 
-    fn should_add_sparse_flag(&self, args: &[OsString]) -> bool {
-        if args.is_empty() {
-            return false;
+    fn get_staged_entries(&self) -> Result<HashMap<PathBuf, Oid>> {
+        // INVARIANT: Reload index from disk, Git may have changed it behind libgit2's back.
+        let mut index = self.repository.index()?;
+        index.read(false)?;
+        let mut entries = HashMap::new();
+
+        for entry in index.iter() {
+            if let Ok(path_str) = std::str::from_utf8(&entry.path) {
+                entries.insert(PathBuf::from(path_str), entry.id);
+            }
         }
 
-        let subcommand = args[0].to_string_lossy();
-        matches!(subcommand.as_ref(), "add" | "rm" | "mv")
+        Ok(entries)
     }
 
-    fn get_staged_paths(&self) -> Result<HashSet<PathBuf>> {
-        let index = self.repository.index()?;
-        let mut paths = HashSet::new();
+    /// Scan content that git-commit is about to commit for secrets.
+    #[instrument(skip_all, level = "debug")]
+    fn scan_commit(&self, work_tree_alias: &WorkTreeAlias, options: CommitOptions) -> Result<()> {
+        let mut index = self.repository.index()?;
+        index.read(false)?;
 
-        for entry in index.iter() {
-            if let Ok(path_str) = std::str::from_utf8(&entry.path) {
-                paths.insert(PathBuf::from(path_str));
+        let mut findings = Vec::new();
+        let head = self
+            .repository
+            .head()
+            .ok()
+            .and_then(|head| head.peel_to_tree().ok());
+        let diff = self
+            .repository
+            .diff_tree_to_index(head.as_ref(), Some(&index), None)?;
+        for delta in diff.deltas() {
+            let file = delta.new_file();
+            let Some(path) = file.path() else {
+                continue;
+            };
+
+            if delta.status() == Delta::Deleted || file.id().is_zero() {
+                continue;
+            }
+
+            let blob = self.repository.find_blob(file.id())?;
+            findings.extend(self.secrets.scan(path, blob.content()));
+        }
+
+        // INVARIANT: Modified tracked files only matter if commit reads from work tree.
+        if options.work_tree {
+            for entry in index.iter() {
+                let path = bytes_to_path(&entry.path);
+                let Ok(content) = read(work_tree_alias.as_path().join(path)) else {
+                    continue;
+                };
+
+                if Oid::hash_object(ObjectType::Blob, &content)? != entry.id {
+                    findings.extend(self.secrets.scan(path, &content));
+                }
+            }
+        }
+
+        if !findings.is_empty() {
+            return Err(SecretsError::Found { findings }.into());
+        }
+
+        Ok(())
+    }
+
+    fn sync_sparse_around<G>(
+        &self,
+        work_tree_alias: &WorkTreeAlias,
+        call: impl FnOnce() -> Result<()>,
+        relock: impl FnOnce() -> Result<G>,
+    ) -> Result<()> {
+        let index_before = self.get_staged_entries()?;
+
+        // INVARIANT: Sync even if call fails, it may have staged changes before failing.
+        let result = call();
+        let synced =
+            relock().and_then(|_guard| self.sync_sparse_since(work_tree_alias, &index_before));
+        if let (Err(_), Err(error)) = (&result, &synced) {
+            warn!("cannot sync sparse rules with index: {error}");
+        }
+
+        result.and(synced)
+    }
+
+    fn sync_sparse_since(
+        &self,
+        work_tree_alias: &WorkTreeAlias,
+        index_before: &HashMap<PathBuf, Oid>,
+    ) -> Result<()> {
+        let index_after = self.get_staged_entries()?;
+
+        // INVARIANT: Sync sparsity rules with index if and only if the index itself has changed.
+        let mut newly_added: Vec<PathBuf> = index_after
+            .keys()
+            .filter(|path| !index_before.contains_key(*path))
+            .cloned()
+            .collect();
+        let mut removed: Vec<PathBuf> = index_before
+            .keys()
+            .filter(|path| !index_after.contains_key(*path))
+            .cloned()
+            .collect();
+        newly_added.sort();
+        removed.sort();
+
+        let renamed = pair_renames(index_before, &index_after, &mut removed, &mut newly_added);
+
+        if !removed.is_empty() || !renamed.is_empty() {
+            let mut uncovered =
+                self.prune_sparse_rules(work_tree_alias, &index_after, &removed, &renamed)?;
+            newly_added.append(&mut uncovered);
+        }
+
+        if !newly_added.is_empty() {
+            self.sync_sparse_with_new_files(work_tree_alias, &newly_added)?;
+        }
+
+        Ok(())
+    }
+
+    /// Prune exact rules of removed paths that no longer match anything.
+    ///
+    /// Exact rules of renamed paths are rewritten to match their new path.
+    /// Returns new paths of renames whose old path had no exact rule, such
+    /// that they can be synced like newly added files.
+    #[instrument(skip_all, level = "debug")]
+    fn prune_sparse_rules(
+        &self,
+        work_tree_alias: &WorkTreeAlias,
+        tracked: &HashMap<PathBuf, Oid>,
+        removed: &[PathBuf],
+        renamed: &[(PathBuf, PathBuf)],
+    ) -> Result<Vec<PathBuf>> {
+        let rules = self.sparsity.current_rules()?;
+        let is_stale = |rule: &String| {
+            rules.contains(rule)
+                && !tracked.keys().any(|path| {
+                    self.sparsity.rule_matches(
+                        work_tree_alias,
+                        work_tree_alias.as_path().join(path),
+                        rule,
+                    )
+                })
+        };
+
+        let mut stale = Vec::new();
+        let mut rewrites = Vec::new();
+        let mut uncovered = Vec::new();
+        for path in removed {
+            let rule = path.display().to_string();
+            if is_stale(&rule) {
+                stale.push(rule);
+            }
+        }
+
+        for (from, to) in renamed {
+            let rule = from.display().to_string();
+            if is_stale(&rule) {
+                debug!("rewrite sparse rule {rule} to {}", to.display());
+                stale.push(rule);
+                rewrites.push(to.display().to_string());
+            } else {
+                uncovered.push(to.clone());
             }
         }
 
-        Ok(paths)
+        if !stale.is_empty() {
+            info!("pruning {} stale sparse rules", stale.len());
+            self.sparsity.edit(|editor| {
+                editor.remove_rules(&stale);
+                editor.insert_rules(rewrites);
+            })?;
+            self.checkout(work_tree_alias)?;
+        }
+
+        Ok(uncovered)
     }
 
     #[instrument(skip(self, new_files), level = "debug")]
@@ -282,13 +569,45 @@ fn sync_sparse_with_new_files(
             info!("adding {} new sparse rules", new_rules.len());
             self.sparsity
                 .edit(|editor| editor.insert_rules(&new_rules))?;
-            syscall_non_interactive("git", self.expand_bin_args(work_tree_alias, ["checkout"]))?;
+            self.checkout(work_tree_alias)?;
         }
 
         Ok(())
     }
 }
 
+/// Pair removed paths with added paths that were renamed from them.
+///
+/// A removed path only counts as renamed if its blob is unique among removed
+/// paths, and reappears under exactly one added path. Otherwise an unrelated
+/// removal and addition of identical content, e.g., two empty files, would be
+/// mistaken for a rename. Paired paths are taken out of both listings.
+fn pair_renames(
+    before: &HashMap<PathBuf, Oid>,
+    after: &HashMap<PathBuf, Oid>,
+    removed: &mut Vec<PathBuf>,
+    added: &mut Vec<PathBuf>,
+) -> Vec<(PathBuf, PathBuf)> {
+    let count = |paths: &[PathBuf], index: &HashMap<PathBuf, Oid>, oid: Oid| {
+        paths.iter().filter(|path| index[*path] == oid).count()
+    };
+
+    let mut renamed = Vec::new();
+    for path in removed.clone() {
+        let oid = before[&path];
+        if count(removed, before, oid) != 1 || count(added, after, oid) != 1 {
+            continue;
+        }
+
+        if let Some(index) = added.iter().position(|new| after[new] == oid) {
+            removed.retain(|old| *old != path);
+            renamed.push((path, added.remove(index)));
+        }
+    }
+
+    renamed
+}
+
 impl Debug for Git2Deployer {
     fn fmt(&self, f: &mut Formatter) -> FmtResult {
         writeln!(f, "gitdir: {:?}", self.repository.path().display())
@@ -314,10 +633,11 @@ fn cat_file(&self, path: impl AsRef<Path>) -> Result<String> {
     ///
     /// Add a new file with content into the cluster's index directly and
     /// commit it with a message. The commit is placed after the most recent
-    /// commit.
+    /// commit. Content is scanned for secrets beforehand.
     ///
     /// # Errors
     ///
+    /// - Return [`Error::Secrets`] if content contains possible secrets.
     /// - Return [`Error::Git2`] if any libgit2 operations fail.
     fn stage_and_commit(
         &self,
@@ -325,6 +645,19 @@ fn stage_and_commit(
         contents: impl AsRef<str>,
         message: impl AsRef<str>,
     ) -> Result<()> {
+        let findings = self
+            .secrets
+            .scan(filename.as_ref(), contents.as_ref().as_bytes());
+        if !findings.is_empty() {
+            return Err(SecretsError::Found { findings }.into());
+        }
+
+        // INVARIANT: Keep sparse checkout state of file that is already tracked.
+        let mut index = self.repository.index()?;
+        let flags_extended = index
+            .get_path(filename.as_ref(), 0)
+            .map_or(0, |entry| entry.flags_extended);
+
         let entry = IndexEntry {
             ctime: IndexTime::new(0, 0),
             mtime: IndexTime::new(0, 0),
@@ -336,7 +669,7 @@ fn stage_and_commit(
             file_size: contents.as_ref().len() as u32,
             id: self.repository.blob(contents.as_ref().as_bytes())?,
             flags: 0,
-            flags_extended: 0,
+            flags_extended,
             path: filename
                 .as_ref()
                 .as_os_str()
@@ -347,8 +680,8 @@ fn stage_and_commit(
         };
 
         // INVARIANT: Always use new tree produced by index after staging new entry.
-        let mut index = self.repository.index()?;
         index.add_frombuffer(&entry, contents.as_ref().as_bytes())?;
+        index.write()?;
         let tree_oid = index.write_tree()?;
         let tree = self.repository.find_tree(tree_oid)?;
 
@@ -417,7 +750,7 @@ fn deploy_with_rules(
         }
 
         self.sparsity.edit(|editor| editor.insert_rules(rules))?;
-        let output = self.gitcall_non_interactive(work_tree_alias, ["checkout"])?;
+        let output = self.checkout(work_tree_alias)?;
         info!("{output}");
 
         Ok(())
@@ -447,7 +780,7 @@ fn undeploy_with_rules(
         }
 
         self.sparsity.edit(|editor| editor.remove_rules(rules))?;
-        let output = self.gitcall_non_interactive(work_tree_alias, ["checkout"])?;
+        let output = self.checkout(work_tree_alias)?;
         info!("{output}");
 
         Ok(())
@@ -476,7 +809,7 @@ fn deploy_all(&self, work_tree_alias: &WorkTreeAlias) -> Result<()> {
             editor.clear_rules();
             editor.insert_rule("/*");
         })?;
-        let output = self.gitcall_non_interactive(work_tree_alias, ["checkout"])?;
+        let output = self.checkout(work_tree_alias)?;
         info!("{output}");
 
         Ok(())
@@ -508,7 +841,7 @@ fn undeploy_all(&self, work_tree_alias: &WorkTreeAlias) -> Result<()> {
         }
 
         self.sparsity.edit(|editor| editor.clear_rules())?;
-        let output = self.gitcall_non_interactive(work_tree_alias, ["checkout"])?;
+        let output = self.checkout(work_tree_alias)?;
         info!("{output}");
 
         Ok(())
@@ -572,16 +905,34 @@ fn is_deployed(&self, work_tree_alias: &WorkTreeAlias) -> bool {
         false
     }
 
+    /// Set mode that sparse checkout operates in.
+    ///
+    /// Only sets `core.sparseCheckoutCone` of the cluster. Current sparsity
+    /// rules are neither touched nor validated, so Git itself decides how to
+    /// treat rules that do not fit the new mode.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::Git2`] if configuration setting cannot be set.
+    fn set_sparse_mode(&self, mode: SparseMode) -> Result<()> {
+        let mut config = self.repository.config()?;
+        config.set_bool("core.sparseCheckoutCone", mode == SparseMode::Cone)?;
+
+        Ok(())
+    }
+
     /// Interact with cluster directly through Git via current process.
     ///
     /// Preserves consistency between sparsity rules and index when caller
     /// uses commands like git-add, git-rm, etc. Blocks current process to allow
-    /// for direct interaction with cluster through Git.
+    /// for direct interaction with cluster through Git. Commits are scanned
+    /// for secrets beforehand, unless `--no-verify` is given.
     ///
     /// # Errors
     ///
     /// - Return [`Error::Syscall`] if system call to Git fails, or Git
     ///   itself fails.
+    /// - Return [`Error::Secrets`] if commit contains possible secrets.
     /// - Return [`Error::Git2`] if any operation on the cluster's index
     ///   fails.
     fn gitcall_interactive(
@@ -589,17 +940,20 @@ fn gitcall_interactive(
         work_tree_alias: &WorkTreeAlias,
         args: impl IntoIterator<Item = impl Into<OsString>>,
     ) -> Result<()> {
-        let index_before = self.get_staged_paths()?;
-        syscall_interactive("git", self.expand_bin_args(work_tree_alias, args))?;
-        let index_after = self.get_staged_paths()?;
+        let args = args.into_iter().map(Into::into).collect::<Vec<OsString>>();
 
-        // INVARIANT: Sync sparsity rules with index if and only if the index itself has changed.
-        let newly_added: Vec<PathBuf> = index_after.difference(&index_before).cloned().collect();
-        if !newly_added.is_empty() {
-            self.sync_sparse_with_new_files(work_tree_alias, &newly_added)?;
+        // INVARIANT: Like hooks, secret scanning is skipped through --no-verify.
+        if let Some(options) = self.parse_git_args(&args)?.commit_options() {
+            if !options.no_verify {
+                self.scan_commit(work_tree_alias, options)?;
+            }
         }
 
-        Ok(())
+        self.sync_sparse_around(
+            work_tree_alias,
+            || syscall_interactive("git", self.expand_bin_args(work_tree_alias, args)?),
+            || Ok(()),
+        )
     }
 
     /// Interact with cluster directly through Git via external process.
@@ -619,7 +973,207 @@ fn gitcall_non_interactive(
         work_tree_alias: &WorkTreeAlias,
         args: impl IntoIterator<Item = impl Into<OsString>>,
     ) -> Result<String> {
-        syscall_non_interactive("git", self.expand_bin_args(work_tree_alias, args))
+        syscall_non_interactive("git", self.expand_bin_args(work_tree_alias, args)?)
+    }
+
+    /// Parse Git passthrough arguments with aliases expanded.
+    ///
+    /// Aliases are looked up in the cluster's configuration.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::Passthrough`] if aliases cannot be expanded.
+    /// - Return [`Error::Git2`] if cluster configuration cannot be read.
+    fn parse_git_args(&self, args: &[OsString]) -> Result<GitArgs> {
+        let mut git_args = GitArgs::parse(args.iter().cloned());
+        let config = self.repository.config()?;
+        git_args.expand_alias(|name| config.get_string(&format!("alias.{name}")).ok())?;
+
+        Ok(git_args)
+    }
+
+    /// Run arbitrary program with cluster as its repository via current
+    /// process.
+    ///
+    /// Exports `GIT_DIR` as the cluster's gitdir, and `GIT_WORK_TREE` as the
+    /// work tree alias, such that any Git aware program operates on the
+    /// cluster. Sparsity rules are kept consistent with the index in the same
+    /// way as [`Git2Deployer::gitcall_interactive`]. Once program exits,
+    /// `relock` is called, and whatever it returns is held while sparsity rules
+    /// are synced, e.g., a lock over the cluster store that program ran
+    /// without.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::Syscall`] if program cannot be spawned, or exits
+    ///   unsuccessfully.
+    /// - Return [`Error::Git2`] if any operation on the cluster's index
+    ///   fails.
+    /// - Fails if `relock` fails, in which case sparsity rules are not
+    ///   synced.
+    fn exec_interactive<G>(
+        &self,
+        work_tree_alias: &WorkTreeAlias,
+        program: impl AsRef<OsStr>,
+        args: impl IntoIterator<Item = impl Into<OsString>>,
+        relock: impl FnOnce() -> Result<G>,
+    ) -> Result<()> {
+        let args = args.into_iter().map(Into::into).collect::<Vec<OsString>>();
+        let envs = [
+            ("GIT_DIR", self.repository.path().as_os_str().to_os_string()),
+            ("GIT_WORK_TREE", work_tree_alias.to_os_string()),
+        ];
+
+        self.sync_sparse_around(
+            work_tree_alias,
+            || syscall_interactive_with_env(program, args, envs),
+            relock,
+        )
+    }
+
+    fn gitdir(&self) -> &Path {
+        self.repository.path()
+    }
+
+    /// Get value of configuration setting of cluster.
+    ///
+    /// Returns [`None`] if setting is not set.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::Git2`] if configuration cannot be read.
+    fn get_config(&self, key: &str) -> Result<Option<String>> {
+        self.get_config_value(&self.repository.config()?, key)
+    }
+
+    /// Set value of configuration setting in local configuration of cluster.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::Git2`] if configuration cannot be written.
+    fn set_config(&self, key: &str, value: &str) -> Result<()> {
+        Ok(self.repository.config()?.set_str(key, value)?)
+    }
+
+    /// Find deployed paths whose mode differs from their declared mode.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::Permissions`] if cluster's index, or modes of
+    ///   deployed paths cannot be read.
+    fn permission_drift(&self, work_tree_alias: &WorkTreeAlias) -> Result<Vec<Drift>> {
+        if self.permissions.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let files = deployed_files(&self.repository, work_tree_alias.as_path())?;
+        Ok(self.permissions.drift(work_tree_alias.as_path(), &files)?)
+    }
+
+    /// Assign tracked files to filter drivers that Oxidot implements.
+    ///
+    /// Registers `oxidot filter` as clean and smudge filter driver for
+    /// encryption and templating in the cluster's configuration, and assigns
+    /// patterns to them in the cluster's `info/attributes` file. The
+    /// encryption driver is also registered as textconv diff driver. Without
+    /// any patterns, the attributes that Oxidot manages are removed, which
+    /// disables filtering.
+    ///
+    /// The encryption key of the cluster store is only generated if
+    /// `generate_key` is set. Otherwise, a missing key is reported, because
+    /// encrypted files cannot be deployed without it.
+    ///
+    /// Writes to the cluster, so the cluster store must be locked exclusively.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::Crypt`] if encryption key cannot be generated.
+    /// - Return [`Error::Syscall`] if attributes file cannot be written.
+    /// - Return [`Error::Git2`] if configuration settings cannot be set.
+    #[instrument(skip_all, level = "debug")]
+    fn set_filters(
+        &self,
+        encrypt: impl IntoIterator<Item = impl AsRef<str>>,
+        templates: impl IntoIterator<Item = impl AsRef<str>>,
+        generate_key: bool,
+    ) -> Result<()> {
+        let encrypt = encrypt
+            .into_iter()
+            .map(|pattern| pattern.as_ref().to_string())
+            .collect::<Vec<_>>();
+        let templates = templates
+            .into_iter()
+            .map(|pattern| pattern.as_ref().to_string())
+            .collect::<Vec<_>>();
+        let gitdir = self.repository.path();
+        let info = gitdir.join("info");
+        let attributes = info.join("attributes");
+        let existing = match read_to_string(&attributes) {
+            Ok(data) => data,
+            Err(error) if error.kind() == ErrorKind::NotFound => String::new(),
+            Err(error) => return Err(error.into()),
+        };
+
+        let entries = encrypt
+            .iter()
+            .map(|pattern| (FilterDriver::Crypt, pattern.as_str()))
+            .chain(
+                templates
+                    .iter()
+                    .map(|pattern| (FilterDriver::Template, pattern.as_str())),
+            );
+        let updated = replace_attributes(&existing, &render_attributes(entries));
+        if updated != existing {
+            debug!("update attributes at {:?}", attributes.display());
+            create_dir_all(&info)?;
+            write(&attributes, updated)?;
+        }
+
+        let mut drivers = Vec::new();
+        if !encrypt.is_empty() {
+            if generate_key {
+                Crypt::load_or_generate(key_file(gitdir))?;
+            } else if let Err(error) = Crypt::load(key_file(gitdir)) {
+                warn!("{error}");
+            }
+            drivers.push(FilterDriver::Crypt);
+        }
+
+        if !templates.is_empty() {
+            drivers.push(FilterDriver::Template);
+        }
+
+        // INVARIANT: Git runs filter drivers through the shell.
+        let gitdir = gitdir.to_string_lossy().replace('\'', r"'\''");
+        let mut config = self.repository.config()?;
+        for driver in drivers {
+            let (name, command) = (driver.name(), driver.command());
+            let mut settings = vec![
+                (
+                    format!("filter.{name}.clean"),
+                    format!("oxidot filter {command} clean '{gitdir}' %f"),
+                ),
+                (
+                    format!("filter.{name}.smudge"),
+                    format!("oxidot filter {command} smudge '{gitdir}' %f"),
+                ),
+                (format!("filter.{name}.required"), "true".into()),
+            ];
+            if driver == FilterDriver::Crypt {
+                settings.push((
+                    format!("diff.{name}.textconv"),
+                    format!("oxidot filter {command} textconv '{gitdir}'"),
+                ));
+            }
+
+            for (key, value) in settings {
+                if self.get_config_value(&config, &key)?.as_deref() != Some(value.as_str()) {
+                    config.set_str(&key, &value)?;
+                }
+            }
+        }
+
+        Ok(())
     }
 }
 
@@ -627,7 +1181,19 @@ fn syscall_interactive(
     cmd: impl AsRef<OsStr>,
     args: impl IntoIterator<Item = impl AsRef<OsStr>>,
 ) -> Result<()> {
-    let status = Command::new(cmd.as_ref()).args(args).spawn()?.wait()?;
+    syscall_interactive_with_env(cmd, args, std::iter::empty::<(&str, &str)>())
+}
+
+fn syscall_interactive_with_env(
+    cmd: impl AsRef<OsStr>,
+    args: impl IntoIterator<Item = impl AsRef<OsStr>>,
+    envs: impl IntoIterator<Item = (impl AsRef<OsStr>, impl AsRef<OsStr>)>,
+) -> Result<()> {
+    let status = Command::new(cmd.as_ref())
+        .args(args)
+        .envs(envs)
+        .spawn()?
+        .wait()?;
     if !status.success() {
         return Err(Error::Syscall(std::io::Error::other(format!(
             "command {:?} failed",
@@ -694,6 +1260,26 @@ pub enum Error {
     #[error(transparent)]
     Sparse(#[from] crate::cluster::sparse::Error),
 
+    /// Secret scanning finds secrets, or cannot be configured.
+    #[error(transparent)]
+    Secrets(#[from] crate::cluster::secrets::Error),
+
+    /// Tracked files cannot be encrypted or decrypted.
+    #[error(transparent)]
+    Crypt(#[from] crate::cluster::crypt::Error),
+
+    /// Declared modes cannot be applied to deployed paths.
+    #[error(transparent)]
+    Permissions(#[from] crate::cluster::permissions::Error),
+
+    /// Git passthrough arguments cannot be handled.
+    #[error(transparent)]
+    Passthrough(#[from] crate::cluster::passthrough::Error),
+
+    /// Cluster store cannot be locked to sync sparsity rules.
+    #[error(transparent)]
+    Lock(#[from] crate::store::lock::Error),
+
     /// Target blob in cluster's index cannot be found.
     #[error("cannot find file blob for {:?}", path.display())]
     BlobNotFound { path: PathBuf },
@@ -709,3 +1295,187 @@ pub enum Error {
 
 /// Friendly result alias :3
 pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cluster::profile::ProfileSelection,
+        config::{ClusterProfile, ClusterSettings},
+    };
+    use pretty_assertions::assert_eq;
+    use sealed_test::prelude::*;
+    use std::collections::BTreeMap;
+
+    fn oid(content: &str) -> Oid {
+        Oid::hash_object(ObjectType::Blob, content.as_bytes()).unwrap()
+    }
+
+    fn deployer(gitdir: &str) -> Result<Git2Deployer> {
+        let repository = Repository::init_bare(gitdir)?;
+        let mut config = repository.config()?;
+        config.set_str("user.name", "blah")?;
+        config.set_str("user.email", "blah@blah.org")?;
+        let sparsity = SparsityDrafter::new(gitdir, InvertedGitignore::new())?;
+
+        Git2Deployer::new(repository, sparsity)
+    }
+
+    #[test]
+    fn pair_renames_requires_unique_blob() {
+        let before = HashMap::from([
+            (PathBuf::from("vimrc"), oid("set nu\n")),
+            ("empty1".into(), oid("")),
+            ("empty2".into(), oid("")),
+        ]);
+        let after = HashMap::from([
+            (PathBuf::from(".vimrc"), oid("set nu\n")),
+            ("empty3".into(), oid("")),
+        ]);
+        let mut removed = vec![PathBuf::from("empty1"), "empty2".into(), "vimrc".into()];
+        let mut added = vec![PathBuf::from(".vimrc"), "empty3".into()];
+
+        let renamed = pair_renames(&before, &after, &mut removed, &mut added);
+        assert_eq!(renamed, vec![(PathBuf::from("vimrc"), ".vimrc".into())]);
+        assert_eq!(removed, vec![PathBuf::from("empty1"), "empty2".into()]);
+        assert_eq!(added, vec![PathBuf::from("empty3")]);
+    }
+
+    #[sealed_test]
+    fn prune_sparse_rules_of_removed_and_renamed_paths() -> Result<()> {
+        let deployer = deployer("cluster.git")?;
+        deployer.stage_and_commit("a", "a\n", "chore: add a")?;
+        deployer.stage_and_commit("b", "b\n", "chore: add b")?;
+        let expect = HashMap::from([(PathBuf::from("a"), oid("a\n")), ("b".into(), oid("b\n"))]);
+        assert_eq!(deployer.get_staged_entries()?, expect);
+
+        std::fs::create_dir("home")?;
+        let work_tree_alias = WorkTreeAlias::new(std::env::current_dir()?.join("home"));
+        deployer
+            .sparsity
+            .edit(|editor| editor.insert_rules(["a", "b"]))?;
+
+        let tracked = HashMap::from([(PathBuf::from("c"), oid("b\n")), ("d".into(), oid("e\n"))]);
+        let uncovered = deployer.prune_sparse_rules(
+            &work_tree_alias,
+            &tracked,
+            &[PathBuf::from("a")],
+            &[("b".into(), "c".into()), ("e".into(), "d".into())],
+        )?;
+        assert_eq!(uncovered, vec![PathBuf::from("d")]);
+        assert_eq!(deployer.sparsity.current_rules()?, vec!["c".to_string()]);
+
+        Ok(())
+    }
+
+    #[sealed_test]
+    fn set_filters_only_generates_key_when_asked() -> Result<()> {
+        let deployer = deployer("store/foo.git")?;
+        let key = key_file(deployer.gitdir());
+
+        deployer.set_filters([".netrc"], [".gitconfig"], false)?;
+        assert!(!key.exists());
+        let attributes = std::fs::read_to_string("store/foo.git/info/attributes")?;
+        assert!(attributes.contains(".netrc filter=oxidot-crypt"));
+        assert!(attributes.contains(".gitconfig filter=oxidot-template"));
+
+        deployer.set_filters([".netrc"], [".gitconfig"], true)?;
+        assert!(key.exists());
+
+        deployer.set_filters(None::<&str>, None::<&str>, false)?;
+        let attributes = std::fs::read_to_string("store/foo.git/info/attributes")?;
+        assert_eq!(attributes, "");
+
+        Ok(())
+    }
+
+    #[sealed_test]
+    fn deploy_profile_excludes_after_includes() -> anyhow::Result<()> {
+        let deployer = deployer("cluster.git")?;
+        deployer.stage_and_commit(".vimrc", "set nu\n", "chore: add vimrc")?;
+        deployer.stage_and_commit(".xinitrc", "exec i3\n", "chore: add xinitrc")?;
+        std::fs::create_dir("home")?;
+
+        // INVARIANT: Nothing is deployed yet, like a freshly cloned cluster.
+        std::fs::remove_file("cluster.git/index")?;
+        let work_tree_alias = WorkTreeAlias::new(std::env::current_dir()?.join("home"));
+
+        let settings = ClusterSettings {
+            include: Some(vec!["/*".into()]),
+            profiles: Some(BTreeMap::from([(
+                "server".into(),
+                ClusterProfile {
+                    exclude: Some(vec!["/.xinitrc".into()]),
+                    ..Default::default()
+                },
+            )])),
+            ..Default::default()
+        };
+        let rules = ProfileSelection::Named(vec!["server".into()])
+            .default_rules(&settings)?
+            .unwrap_or_default();
+        deployer.deploy_with_rules(&work_tree_alias, rules)?;
+
+        let result = std::fs::read_to_string("cluster.git/info/sparse-checkout")?;
+        assert_eq!(result, "/*\n!/.xinitrc\n");
+        assert!(work_tree_alias.as_path().join(".vimrc").exists());
+        assert!(!work_tree_alias.as_path().join(".xinitrc").exists());
+
+        Ok(())
+    }
+
+    #[sealed_test]
+    fn exec_interactive_exports_cluster_and_syncs_sparse_rules() -> Result<()> {
+        let deployer = deployer("cluster.git")?;
+        deployer.stage_and_commit(".bashrc", "set -o vi\n", "chore: add bashrc")?;
+        std::fs::create_dir("home")?;
+        std::fs::write("home/.vimrc", "set nu\n")?;
+        let work_tree_alias = WorkTreeAlias::new(std::env::current_dir()?.join("home"));
+
+        let script = r#"printf '%s\n%s\n' "$GIT_DIR" "$GIT_WORK_TREE" > env &&
+            git add --sparse "$GIT_WORK_TREE/.vimrc""#;
+        deployer.exec_interactive(&work_tree_alias, "sh", ["-c", script], || Ok(()))?;
+
+        let expect = format!(
+            "{}\n{}\n",
+            deployer.gitdir().display(),
+            work_tree_alias.as_path().display()
+        );
+        assert_eq!(std::fs::read_to_string("env")?, expect);
+        assert_eq!(deployer.list_deploy_rules()?, vec![".vimrc"]);
+
+        Ok(())
+    }
+
+    #[sealed_test]
+    fn add_remotes_keeps_existing_remotes() -> Result<()> {
+        let deployer = deployer("foo.git")?;
+        let url_rewrites = UrlRewrites::from_iter([("https://mirror.org/", "https://blah.org/")]);
+        deployer.add_remotes(
+            [
+                ("upstream", "https://blah.org/upstream.git"),
+                ("backup", "https://mirror.org/backup.git"),
+            ],
+            &url_rewrites,
+        )?;
+        deployer.add_remotes(
+            [("upstream", "https://blah.org/changed.git")],
+            &url_rewrites,
+        )?;
+
+        let url = |name| -> Result<Option<String>> {
+            let remote = deployer.repository.find_remote(name)?;
+            Ok(remote.url().map(str::to_owned))
+        };
+        assert_eq!(
+            url("upstream")?.as_deref(),
+            Some("https://blah.org/upstream.git")
+        );
+        assert_eq!(
+            url("backup")?.as_deref(),
+            Some("https://blah.org/backup.git")
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,180 @@
+// SPDX-FileCopyrightText: 2025 Jason Pena <jasonpena@awkless.com>
+// SPDX-License-Identifier: MIT
+
+//! Git filter drivers implemented by Oxidot.
+//!
+//! Some tracked files cannot be deployed as is. Encrypted files must be
+//! decrypted, and templates must be rendered. Oxidot handles both through
+//! Git's clean and smudge filters, such that plain Git commands on a cluster
+//! keep working transparently.
+//!
+//! # Registration
+//!
+//! Filter drivers are registered in the cluster's configuration as calls to
+//! the hidden `oxidot filter` command, thus `oxidot` must be found in `PATH`.
+//! Paths are assigned to filter drivers in the cluster's `info/attributes`
+//! file within a block of lines that Oxidot manages. Attributes the user sets
+//! outside of that block are left alone.
+//!
+//! Git only allows one filter per path. If a path is both encrypted and
+//! templated, then encryption wins.
+//!
+//! # See Also
+//!
+//! 1. [`crate::cluster::crypt`]
+//! 2. [`crate::cluster::template`]
+
+use git2::Repository;
+use std::path::Path;
+
+/// Start of attributes managed by Oxidot.
+pub const ATTRIBUTES_BEGIN: &str = "# BEGIN oxidot filters";
+
+/// End of attributes managed by Oxidot.
+pub const ATTRIBUTES_END: &str = "# END oxidot filters";
+
+/// Tracked files that must never be filtered.
+///
+/// Oxidot reads these straight from the object database.
+pub const PLAIN_FILES: [&str; 2] = ["cluster.toml", ".oxidotignore"];
+
+/// Filter drivers that Oxidot implements.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FilterDriver {
+    /// Encrypt and decrypt tracked files.
+    Crypt,
+
+    /// Render templates.
+    Template,
+}
+
+impl FilterDriver {
+    /// Name of filter driver in cluster configuration and attributes.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Crypt => "oxidot-crypt",
+            Self::Template => "oxidot-template",
+        }
+    }
+
+    /// Name of filter driver on the command-line of `oxidot filter`.
+    pub fn command(self) -> &'static str {
+        match self {
+            Self::Crypt => "crypt",
+            Self::Template => "template",
+        }
+    }
+}
+
+/// Render attributes that assign paths to filter drivers.
+///
+/// Lines are wrapped in markers such that they can be replaced without
+/// touching attributes the user set. Returns an empty string if there is
+/// nothing to assign.
+pub fn render_attributes<'a>(entries: impl IntoIterator<Item = (FilterDriver, &'a str)>) -> String {
+    let mut entries = entries.into_iter().collect::<Vec<_>>();
+    if entries.is_empty() {
+        return String::new();
+    }
+
+    // INVARIANT: Later lines win, so encryption must come last.
+    entries.sort_by_key(|(driver, _)| *driver == FilterDriver::Crypt);
+
+    let mut lines = vec![ATTRIBUTES_BEGIN.to_string()];
+    for (driver, pattern) in entries {
+        let name = driver.name();
+        match driver {
+            FilterDriver::Crypt => lines.push(format!("{pattern} filter={name} diff={name}")),
+            FilterDriver::Template => lines.push(format!("{pattern} filter={name}")),
+        }
+    }
+
+    lines.extend(
+        PLAIN_FILES
+            .iter()
+            .map(|file| format!("/{file} !filter !diff")),
+    );
+    lines.push(ATTRIBUTES_END.to_string());
+
+    lines.join("\n") + "\n"
+}
+
+/// Replace attributes managed by Oxidot in existing attributes file content.
+///
+/// If `managed` is empty, then managed attributes are removed entirely.
+pub fn replace_attributes(existing: &str, managed: &str) -> String {
+    let mut result = String::new();
+    let mut inside = false;
+    for line in existing.lines() {
+        match line {
+            ATTRIBUTES_BEGIN => inside = true,
+            ATTRIBUTES_END => inside = false,
+            line if !inside => {
+                result.push_str(line);
+                result.push('\n');
+            }
+            _ => continue,
+        }
+    }
+
+    result.push_str(managed);
+    result
+}
+
+/// Look up content currently staged for path in cluster at gitdir.
+///
+/// Path is relative to top-level of cluster. Filters use staged content to
+/// avoid reporting changes that only exist because of filtering.
+///
+/// # Errors
+///
+/// - Return [`git2::Error`] if cluster or its index cannot be read.
+pub fn staged_content(
+    gitdir: impl AsRef<Path>,
+    path: impl AsRef<Path>,
+) -> Result<Option<Vec<u8>>, git2::Error> {
+    let repository = Repository::open_bare(gitdir.as_ref())?;
+    let index = repository.index()?;
+    let Some(entry) = index.get_path(path.as_ref(), 0) else {
+        return Ok(None);
+    };
+
+    let blob = repository.find_blob(entry.id)?;
+    Ok(Some(blob.content().to_vec()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn replace_attributes_keeps_user_lines() {
+        let existing = indoc! {"
+            *.md diff=markdown
+            # BEGIN oxidot filters
+            .old filter=oxidot-crypt diff=oxidot-crypt
+            # END oxidot filters
+        "};
+
+        let managed = render_attributes([
+            (FilterDriver::Crypt, ".netrc"),
+            (FilterDriver::Template, ".gitconfig"),
+        ]);
+        let result = replace_attributes(existing, &managed);
+        let expect = indoc! {"
+            *.md diff=markdown
+            # BEGIN oxidot filters
+            .gitconfig filter=oxidot-template
+            .netrc filter=oxidot-crypt diff=oxidot-crypt
+            /cluster.toml !filter !diff
+            /.oxidotignore !filter !diff
+            # END oxidot filters
+        "};
+        assert_eq!(result, expect);
+
+        let result = replace_attributes(expect, &render_attributes([]));
+        assert_eq!(result, "*.md diff=markdown\n");
+    }
+}
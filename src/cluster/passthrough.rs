@@ -0,0 +1,574 @@
+// SPDX-FileCopyrightText: 2025 Jason Pena <jasonpena@awkless.com>
+// SPDX-License-Identifier: MIT
+
+//! Git passthrough argument handling.
+//!
+//! Oxidot lets the user interact with a cluster by passing arguments straight
+//! through to Git. Some Git commands need extra arguments to behave properly
+//! with sparse checkout, e.g., git-add must be given the `--sparse` flag to
+//! stage files outside of the current sparsity rules. Thus, Oxidot needs to
+//! figure out which Git command is actually being invoked before it can
+//! decide where to place any extra arguments.
+//!
+//! # Command-Line Layout
+//!
+//! Git expects its command-line arguments in the following layout:
+//!
+//! ```text
+//! git [<global-options>] <command> [<args>]
+//! ```
+//!
+//! Some global options take a value as a separate argument, e.g.,
+//! `-c color.ui=always`, or `-C <path>`. Thus, the first argument that does
+//! not start with a dash is not always the command.
+//!
+//! # Aliases
+//!
+//! The command itself may be an alias defined in the cluster's configuration,
+//! or through `-c alias.<name>=<value>` on the command-line. Aliases are
+//! expanded just like Git expands them, such that an alias that expands to
+//! git-add still gets the `--sparse` flag. Aliases that start with "!" run
+//! shell commands, and are never expanded. Git also ignores aliases that hide
+//! builtin commands, which Oxidot mirrors.
+
+use std::ffi::{OsStr, OsString};
+
+/// Maximum number of alias expansions before giving up.
+pub const ALIAS_DEPTH_LIMIT: usize = 16;
+
+/// Git commands that require the `--sparse` flag to work outside of sparsity
+/// rules, where git-stage is a synonym of git-add.
+const SPARSE_COMMANDS: [&str; 4] = ["add", "stage", "rm", "mv"];
+
+/// Global options of Git that take their value as a separate argument.
+const VALUE_OPTIONS: [&str; 7] = [
+    "-c",
+    "-C",
+    "--git-dir",
+    "--work-tree",
+    "--namespace",
+    "--config-env",
+    "--attr-source",
+];
+
+/// Builtin Git commands that cannot be hidden by aliases.
+const BUILTINS: [&str; 63] = [
+    "add",
+    "am",
+    "annotate",
+    "apply",
+    "archive",
+    "bisect",
+    "blame",
+    "branch",
+    "bundle",
+    "cat-file",
+    "check-ignore",
+    "checkout",
+    "cherry",
+    "cherry-pick",
+    "clean",
+    "clone",
+    "commit",
+    "config",
+    "describe",
+    "diff",
+    "difftool",
+    "fetch",
+    "format-patch",
+    "fsck",
+    "gc",
+    "grep",
+    "help",
+    "init",
+    "log",
+    "ls-files",
+    "ls-remote",
+    "ls-tree",
+    "maintenance",
+    "merge",
+    "mergetool",
+    "mv",
+    "notes",
+    "pull",
+    "push",
+    "range-diff",
+    "rebase",
+    "reflog",
+    "remote",
+    "repack",
+    "replace",
+    "reset",
+    "restore",
+    "rev-list",
+    "rev-parse",
+    "revert",
+    "rm",
+    "shortlog",
+    "show",
+    "show-ref",
+    "sparse-checkout",
+    "stage",
+    "stash",
+    "status",
+    "submodule",
+    "switch",
+    "tag",
+    "version",
+    "worktree",
+];
+
+/// Short options of git-commit that take a value.
+const COMMIT_SHORT_VALUE_OPTIONS: &str = "mFCct";
+
+/// Long options of git-commit that take a value.
+const COMMIT_LONG_VALUE_OPTIONS: [&str; 11] = [
+    "--message",
+    "--file",
+    "--reuse-message",
+    "--reedit-message",
+    "--template",
+    "--author",
+    "--date",
+    "--fixup",
+    "--squash",
+    "--cleanup",
+    "--trailer",
+];
+
+/// How git-commit was asked to commit.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CommitOptions {
+    /// Hooks and other pre-commit checks should be skipped.
+    pub no_verify: bool,
+
+    /// Content is taken from work tree instead of just the index.
+    pub work_tree: bool,
+}
+
+/// Git command-line arguments split around the command.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct GitArgs {
+    /// Global options that come before the command.
+    pub globals: Vec<OsString>,
+
+    /// Command, or alias of a command.
+    pub command: Option<OsString>,
+
+    /// Everything after the command.
+    pub args: Vec<OsString>,
+}
+
+impl GitArgs {
+    /// Split Git command-line arguments around the command.
+    pub fn parse(args: impl IntoIterator<Item = impl Into<OsString>>) -> Self {
+        let mut args = args.into_iter().map(Into::into);
+        let mut globals = Vec::new();
+
+        while let Some(arg) = args.next() {
+            let text = arg.to_string_lossy();
+            if !text.starts_with('-') {
+                return Self {
+                    globals,
+                    command: Some(arg),
+                    args: args.collect(),
+                };
+            }
+
+            // INVARIANT: Everything after "--" belongs to Git itself, not to a command.
+            if text == "--" {
+                globals.push(arg);
+                globals.extend(args);
+                break;
+            }
+
+            let takes_value = VALUE_OPTIONS.contains(&text.as_ref());
+            globals.push(arg);
+            if takes_value {
+                globals.extend(args.next());
+            }
+        }
+
+        Self {
+            globals,
+            command: None,
+            args: Vec::new(),
+        }
+    }
+
+    /// Expand command if it is an alias.
+    ///
+    /// Aliases given through `-c alias.<name>=<value>` take precedence over
+    /// the `lookup` function, which should query the cluster's configuration.
+    /// Expansion repeats until the command is no longer an alias. Shell
+    /// aliases, and aliases that hide builtin commands, are left untouched.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::AliasLoop`] if aliases keep expanding beyond
+    ///   [`ALIAS_DEPTH_LIMIT`].
+    /// - Return [`Error::AliasQuote`] if alias contains unclosed quotes.
+    pub fn expand_alias(&mut self, lookup: impl Fn(&str) -> Option<String>) -> Result<()> {
+        let mut depth = 0;
+        while let Some(name) = self.command.as_ref().and_then(|command| command.to_str()) {
+            if BUILTINS.contains(&name) {
+                break;
+            }
+
+            let Some(value) = self.config_alias(name).or_else(|| lookup(name)) else {
+                break;
+            };
+
+            if value.trim_start().starts_with('!') {
+                break;
+            }
+
+            if depth == ALIAS_DEPTH_LIMIT {
+                return Err(Error::AliasLoop { alias: name.into() });
+            }
+            depth += 1;
+
+            let mut words = split_alias(&value)
+                .ok_or_else(|| Error::AliasQuote { alias: name.into() })?
+                .into_iter()
+                .map(OsString::from);
+            let Some(command) = words.next() else {
+                break;
+            };
+
+            let mut args = words.collect::<Vec<_>>();
+            args.append(&mut self.args);
+            self.command = Some(command);
+            self.args = args;
+        }
+
+        Ok(())
+    }
+
+    /// Insert `--sparse` flag right after command if it needs one.
+    ///
+    /// Returns true if flag was inserted. Flag is never duplicated, and is
+    /// never placed after a "--" separator.
+    pub fn insert_sparse_flag(&mut self) -> bool {
+        let Some(command) = self.command.as_ref().and_then(|command| command.to_str()) else {
+            return false;
+        };
+
+        if !SPARSE_COMMANDS.contains(&command) {
+            return false;
+        }
+
+        let has_flag = self
+            .args
+            .iter()
+            .take_while(|arg| *arg != "--")
+            .any(|arg| arg == "--sparse");
+        if has_flag {
+            return false;
+        }
+
+        self.args.insert(0, "--sparse".into());
+        true
+    }
+
+    /// Determine how git-commit would commit given current arguments.
+    ///
+    /// Returns [`None`] if command is not git-commit.
+    pub fn commit_options(&self) -> Option<CommitOptions> {
+        if self.command.as_ref()? != "commit" {
+            return None;
+        }
+
+        let mut options = CommitOptions::default();
+        let mut args = self.args.iter().map(|arg| arg.to_string_lossy());
+        while let Some(arg) = args.next() {
+            match arg.as_ref() {
+                "--" => {
+                    options.work_tree |= args.next().is_some();
+                    break;
+                }
+                "--no-verify" => options.no_verify = true,
+                "--all" | "--include" | "--only" => options.work_tree = true,
+                arg if COMMIT_LONG_VALUE_OPTIONS.contains(&arg) => {
+                    args.next();
+                }
+                arg if arg.starts_with("--") => continue,
+                arg if arg.starts_with('-') => {
+                    // INVARIANT: Short flags may be bundled, and end at the first flag taking a value.
+                    for (index, flag) in arg.char_indices().skip(1) {
+                        match flag {
+                            'n' => options.no_verify = true,
+                            'a' | 'i' | 'o' => options.work_tree = true,
+                            flag if COMMIT_SHORT_VALUE_OPTIONS.contains(flag) => {
+                                if index + flag.len_utf8() == arg.len() {
+                                    args.next();
+                                }
+                                break;
+                            }
+                            _ => continue,
+                        }
+                    }
+                }
+                _ => options.work_tree = true,
+            }
+        }
+
+        Some(options)
+    }
+
+    /// Join arguments back into one listing.
+    pub fn into_args(self) -> Vec<OsString> {
+        let mut args = self.globals;
+        args.extend(self.command);
+        args.extend(self.args);
+
+        args
+    }
+
+    fn config_alias(&self, name: &str) -> Option<String> {
+        let key = format!("alias.{}=", name.to_lowercase());
+        let mut found = None;
+
+        // INVARIANT: Last configuration override wins, just like Git.
+        let mut globals = self.globals.iter();
+        while let Some(arg) = globals.next() {
+            if arg != OsStr::new("-c") {
+                continue;
+            }
+
+            let Some(setting) = globals.next().and_then(|setting| setting.to_str()) else {
+                continue;
+            };
+
+            let Some((setting_key, value)) = setting.split_once('=') else {
+                continue;
+            };
+
+            if format!("{}=", setting_key.to_lowercase()) == key {
+                found = Some(value.to_string());
+            }
+        }
+
+        found
+    }
+}
+
+/// Split alias value into words like Git does.
+///
+/// Honors single quotes, double quotes, and backslash escapes. Returns
+/// [`None`] if a quote is left unclosed.
+fn split_alias(value: &str) -> Option<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut quote = None;
+    let mut chars = value.chars();
+
+    while let Some(ch) = chars.next() {
+        match (quote, ch) {
+            (Some(open), ch) if ch == open => quote = None,
+            (None, '\'' | '"') => {
+                quote = Some(ch);
+                in_word = true;
+            }
+            (None | Some('"'), '\\') => {
+                word.extend(chars.next());
+                in_word = true;
+            }
+            (None, ch) if ch.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            (_, ch) => {
+                word.push(ch);
+                in_word = true;
+            }
+        }
+    }
+
+    if quote.is_some() {
+        return None;
+    }
+
+    if in_word {
+        words.push(word);
+    }
+
+    Some(words)
+}
+
+/// Git passthrough error types.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Alias keeps expanding into other aliases.
+    #[error("alias {alias:?} expands too many times, is it recursive?")]
+    AliasLoop { alias: String },
+
+    /// Alias contains unclosed quote.
+    #[error("alias {alias:?} contains an unclosed quote")]
+    AliasQuote { alias: String },
+}
+
+/// Friendly result alias :3
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn to_args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    fn no_alias(_: &str) -> Option<String> {
+        None
+    }
+
+    #[test]
+    fn git_args_parse_global_options() {
+        let result = GitArgs::parse([
+            "-c",
+            "color.ui=always",
+            "--no-pager",
+            "-C",
+            "/tmp",
+            "--git-dir=/tmp/foo.git",
+            "add",
+            "-p",
+        ]);
+        let expect = GitArgs {
+            globals: to_args(&[
+                "-c",
+                "color.ui=always",
+                "--no-pager",
+                "-C",
+                "/tmp",
+                "--git-dir=/tmp/foo.git",
+            ]),
+            command: Some("add".into()),
+            args: to_args(&["-p"]),
+        };
+        assert_eq!(result, expect);
+
+        let result = GitArgs::parse(["--version"]);
+        let expect = GitArgs {
+            globals: to_args(&["--version"]),
+            command: None,
+            args: Vec::new(),
+        };
+        assert_eq!(result, expect);
+    }
+
+    #[test]
+    fn git_args_insert_sparse_flag() {
+        let mut args = GitArgs::parse(["-c", "color.ui=always", "add", "foo"]);
+        assert!(args.insert_sparse_flag());
+        assert_eq!(
+            args.into_args(),
+            to_args(&["-c", "color.ui=always", "add", "--sparse", "foo"])
+        );
+
+        let mut args = GitArgs::parse(["stage", "foo"]);
+        assert!(args.insert_sparse_flag());
+        assert_eq!(args.into_args(), to_args(&["stage", "--sparse", "foo"]));
+
+        let mut args = GitArgs::parse(["add", "--", "--sparse"]);
+        assert!(args.insert_sparse_flag());
+        assert_eq!(
+            args.into_args(),
+            to_args(&["add", "--sparse", "--", "--sparse"])
+        );
+
+        // No duplication.
+        let mut args = GitArgs::parse(["rm", "--sparse", "foo"]);
+        assert!(!args.insert_sparse_flag());
+        assert_eq!(args.into_args(), to_args(&["rm", "--sparse", "foo"]));
+
+        let mut args = GitArgs::parse(["status"]);
+        assert!(!args.insert_sparse_flag());
+        assert_eq!(args.into_args(), to_args(&["status"]));
+    }
+
+    #[test]
+    fn git_args_expand_alias() -> Result<()> {
+        let lookup = |name: &str| match name {
+            "a" => Some("add -v".into()),
+            "aa" => Some("a --all".into()),
+            "sh" => Some("!echo add".into()),
+            "status" => Some("add".into()),
+            "quoted" => Some(r#"commit -m "two words""#.into()),
+            _ => None,
+        };
+
+        let mut args = GitArgs::parse(["aa", "foo"]);
+        args.expand_alias(lookup)?;
+        assert!(args.insert_sparse_flag());
+        assert_eq!(
+            args.into_args(),
+            to_args(&["add", "--sparse", "-v", "--all", "foo"])
+        );
+
+        let mut args = GitArgs::parse(["quoted"]);
+        args.expand_alias(lookup)?;
+        assert_eq!(args.into_args(), to_args(&["commit", "-m", "two words"]));
+
+        // Shell aliases, and aliases hiding builtins, are not expanded.
+        for name in ["sh", "status"] {
+            let mut args = GitArgs::parse([name]);
+            args.expand_alias(lookup)?;
+            assert_eq!(args.into_args(), to_args(&[name]));
+        }
+
+        // Command-line aliases take precedence.
+        let mut args = GitArgs::parse(["-c", "alias.A=mv", "a", "foo", "bar"]);
+        args.expand_alias(lookup)?;
+        assert!(args.insert_sparse_flag());
+        assert_eq!(
+            args.into_args(),
+            to_args(&["-c", "alias.A=mv", "mv", "--sparse", "foo", "bar"])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn git_args_commit_options() {
+        let args = GitArgs::parse(["commit", "-am", "-n"]);
+        let expect = CommitOptions {
+            no_verify: false,
+            work_tree: true,
+        };
+        assert_eq!(args.commit_options(), Some(expect));
+
+        let args = GitArgs::parse(["commit", "--no-verify", "-m", "foo", "--", "bar"]);
+        let expect = CommitOptions {
+            no_verify: true,
+            work_tree: true,
+        };
+        assert_eq!(args.commit_options(), Some(expect));
+
+        let args = GitArgs::parse(["commit", "--message", "a", "-nvmfoo"]);
+        let expect = CommitOptions {
+            no_verify: true,
+            work_tree: false,
+        };
+        assert_eq!(args.commit_options(), Some(expect));
+
+        let args = GitArgs::parse(["status"]);
+        assert_eq!(args.commit_options(), None);
+    }
+
+    #[test]
+    fn git_args_expand_alias_errors() {
+        let mut args = GitArgs::parse(["-c", "alias.loop=loop", "loop"]);
+        let result = args.expand_alias(no_alias);
+        assert!(matches!(result, Err(Error::AliasLoop { .. })));
+
+        let mut args = GitArgs::parse(["-c", "alias.bad=add 'foo", "bad"]);
+        let result = args.expand_alias(no_alias);
+        assert!(matches!(result, Err(Error::AliasQuote { .. })));
+    }
+}
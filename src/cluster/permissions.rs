@@ -0,0 +1,387 @@
+// SPDX-FileCopyrightText: 2025 Jason Pena <jasonpena@awkless.com>
+// SPDX-License-Identifier: MIT
+
+//! File mode enforcement for deployed files.
+//!
+//! Git only records the executable bit of tracked files. Everything else is
+//! left to the umask of whoever deploys, which is far too loose for files like
+//! `~/.ssh/config` or `~/.netrc`, and directories like `~/.gnupg`. Thus,
+//! cluster definitions can declare the modes that deployed paths must have.
+//!
+//! # Permissions
+//!
+//! The `[settings.permissions]` table of the cluster definition maps patterns
+//! to octal modes, e.g., `".ssh" = "700"` and `".ssh/*" = "600"`. Patterns are
+//! globs relative to the work tree alias, where `*` never matches `/`. If more
+//! than one pattern matches a path, then the longest pattern wins. Patterns
+//! with a trailing `/` only match directories.
+//!
+//! Directories need their execute bits to be entered. Thus, a mode applied to
+//! a directory always keeps execute bits for everyone that may read it, e.g.,
+//! `".ssh/*" = "600"` gives a nested `.ssh/keys` directory mode 700.
+//!
+//! # Enforcement
+//!
+//! Modes are applied to deployed files, and to the directories between them
+//! and the work tree alias, after every checkout of the cluster. Symbolic
+//! links are left alone. Paths whose mode drifted away from the declared mode
+//! afterwards are reported by `oxidot doctor` and `oxidot status`. Modes are
+//! only enforced on Unix.
+
+use git2::{IndexEntryExtendedFlag, Repository};
+use glob::{MatchOptions, Pattern};
+use std::{
+    collections::BTreeMap,
+    fmt::{Display, Formatter, Result as FmtResult},
+    path::{Path, PathBuf},
+};
+use tracing::debug;
+
+/// Modes that deployed paths must have.
+#[derive(Debug, Default, Clone)]
+pub struct Permissions {
+    rules: Vec<Rule>,
+}
+
+/// Pattern mapped to the mode that matching paths must have.
+#[derive(Debug, Clone)]
+struct Rule {
+    glob: Pattern,
+    mode: u32,
+    dirs_only: bool,
+}
+
+impl Permissions {
+    /// Construct new permissions from patterns mapped to octal modes.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::InvalidPattern`] if any pattern is invalid.
+    /// - Return [`Error::InvalidMode`] if any mode is not an octal mode.
+    pub fn new(
+        entries: impl IntoIterator<Item = (impl AsRef<str>, impl AsRef<str>)>,
+    ) -> Result<Self> {
+        let mut rules = Vec::new();
+        for (pattern, mode) in entries {
+            let (pattern, mode) = (pattern.as_ref(), mode.as_ref());
+            let dirs_only = pattern.ends_with('/');
+            let trimmed = pattern.trim_start_matches('/').trim_end_matches('/');
+            let glob = Pattern::new(trimmed).map_err(|error| Error::InvalidPattern {
+                pattern: pattern.into(),
+                source: error,
+            })?;
+            let parsed = u32::from_str_radix(mode.trim_start_matches("0o"), 8)
+                .ok()
+                .filter(|mode| *mode <= 0o7777)
+                .ok_or_else(|| Error::InvalidMode {
+                    pattern: pattern.into(),
+                    mode: mode.into(),
+                })?;
+            rules.push(Rule {
+                glob,
+                mode: parsed,
+                dirs_only,
+            });
+        }
+
+        // INVARIANT: Longest pattern comes first, so it wins.
+        rules.sort_by_key(|rule| std::cmp::Reverse(rule.glob.as_str().len()));
+
+        Ok(Self { rules })
+    }
+
+    /// Check if no modes are declared.
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Determine declared mode of file or directory relative to work tree
+    /// alias.
+    ///
+    /// Modes of directories keep execute bits for everyone that may read
+    /// them.
+    pub fn mode_for(&self, path: impl AsRef<Path>, is_dir: bool) -> Option<u32> {
+        let options = MatchOptions {
+            require_literal_separator: true,
+            ..MatchOptions::default()
+        };
+
+        let mode = self
+            .rules
+            .iter()
+            .filter(|rule| is_dir || !rule.dirs_only)
+            .find(|rule| rule.glob.matches_path_with(path.as_ref(), options))
+            .map(|rule| rule.mode)?;
+
+        // INVARIANT: Directories that can be read can also be entered.
+        if is_dir {
+            Some(mode | ((mode & 0o444) >> 2))
+        } else {
+            Some(mode)
+        }
+    }
+
+    /// Apply declared modes to deployed files and their directories.
+    ///
+    /// Files are relative to work tree alias. Only paths whose mode differs
+    /// are touched.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::Io`] if mode of any path cannot be read or set.
+    pub fn apply(&self, work_tree_alias: impl AsRef<Path>, files: &[PathBuf]) -> Result<()> {
+        for drift in self.drift(work_tree_alias, files)? {
+            drift.repair()?;
+        }
+
+        Ok(())
+    }
+
+    /// Find deployed files and directories whose mode differs from their
+    /// declared mode.
+    ///
+    /// Files are relative to work tree alias.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::Io`] if mode of any path cannot be read.
+    pub fn drift(&self, work_tree_alias: impl AsRef<Path>, files: &[PathBuf]) -> Result<Vec<Drift>> {
+        if self.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut targets = BTreeMap::new();
+        for file in files {
+            targets.insert(file.clone(), false);
+            targets.extend(
+                file.ancestors()
+                    .skip(1)
+                    .filter(|path| !path.as_os_str().is_empty())
+                    .map(|path| (path.to_path_buf(), true)),
+            );
+        }
+
+        let mut drifted = Vec::new();
+        for (target, is_dir) in targets {
+            let Some(expect) = self.mode_for(&target, is_dir) else {
+                continue;
+            };
+
+            let path = work_tree_alias.as_ref().join(&target);
+            if let Some(found) = current_mode(&path)? {
+                if found != expect {
+                    drifted.push(Drift {
+                        path,
+                        expect,
+                        found,
+                    });
+                }
+            }
+        }
+
+        Ok(drifted)
+    }
+}
+
+/// Deployed path whose mode differs from its declared mode.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Drift {
+    /// Path in work tree alias.
+    pub path: PathBuf,
+
+    /// Declared mode.
+    pub expect: u32,
+
+    /// Current mode.
+    pub found: u32,
+}
+
+impl Drift {
+    /// Set path to its declared mode.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::Io`] if mode cannot be set.
+    pub fn repair(&self) -> Result<()> {
+        debug!("set mode of {:?} to {:o}", self.path.display(), self.expect);
+        set_mode(&self.path, self.expect)
+    }
+}
+
+impl Display for Drift {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> FmtResult {
+        write!(
+            fmt,
+            "{:?} has mode {:o}, expected {:o}",
+            self.path.display(),
+            self.found,
+            self.expect
+        )
+    }
+}
+
+/// List tracked files of cluster that are deployed to work tree alias.
+///
+/// Tracked files are deployed if sparse checkout did not skip them, and they
+/// exist in the work tree alias. Paths are relative to work tree alias.
+///
+/// # Errors
+///
+/// - Return [`Error::Git2`] if cluster's index cannot be read.
+pub fn deployed_files(
+    repository: &Repository,
+    work_tree_alias: impl AsRef<Path>,
+) -> Result<Vec<PathBuf>> {
+    let index = repository.index()?;
+    let files = index
+        .iter()
+        .filter(|entry| {
+            !IndexEntryExtendedFlag::from_bits_truncate(entry.flags_extended).is_skip_worktree()
+        })
+        .map(|entry| PathBuf::from(String::from_utf8_lossy(&entry.path).into_owned()))
+        .filter(|path| work_tree_alias.as_ref().join(path).exists())
+        .collect();
+
+    Ok(files)
+}
+
+#[cfg(unix)]
+fn current_mode(path: &Path) -> Result<Option<u32>> {
+    use std::os::unix::fs::PermissionsExt;
+
+    // INVARIANT: Symbolic links are never followed.
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_symlink() => Ok(None),
+        Ok(metadata) => Ok(Some(metadata.permissions().mode() & 0o7777)),
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error.into()),
+    }
+}
+
+#[cfg(not(unix))]
+fn current_mode(_path: &Path) -> Result<Option<u32>> {
+    Ok(None)
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: u32) -> Result<()> {
+    Ok(())
+}
+
+/// File mode enforcement error types.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Pattern of permissions is not a valid glob.
+    #[error("invalid permissions pattern {pattern:?}: {source}")]
+    InvalidPattern {
+        pattern: String,
+        source: glob::PatternError,
+    },
+
+    /// Mode of permissions is not an octal mode.
+    #[error("invalid mode {mode:?} for permissions pattern {pattern:?}")]
+    InvalidMode { pattern: String, mode: String },
+
+    /// Operations from libgit2 fail.
+    #[error(transparent)]
+    Git2(#[from] git2::Error),
+
+    /// Modes cannot be read or set.
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// Friendly result alias :3
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use sealed_test::prelude::*;
+    use std::{
+        fs::{create_dir_all, write},
+        os::unix::fs::PermissionsExt,
+    };
+
+    fn mode(path: &str) -> u32 {
+        std::fs::metadata(path).unwrap().permissions().mode() & 0o7777
+    }
+
+    #[sealed_test]
+    fn permissions_apply_and_drift() -> Result<()> {
+        create_dir_all("home/.ssh")?;
+        write("home/.ssh/config", "Host *\n")?;
+        write("home/.ssh/known_hosts", "\n")?;
+        write("home/.bashrc", "\n")?;
+        set_mode(Path::new("home/.ssh/known_hosts"), 0o644)?;
+
+        let permissions = Permissions::new([
+            ("/.ssh", "700"),
+            (".ssh/*", "0600"),
+            (".ssh/known_hosts", "0o644"),
+        ])?;
+        let files = vec![
+            PathBuf::from(".ssh/config"),
+            ".ssh/known_hosts".into(),
+            ".bashrc".into(),
+        ];
+
+        permissions.apply("home", &files)?;
+        assert_eq!(mode("home/.ssh"), 0o700);
+        assert_eq!(mode("home/.ssh/config"), 0o600);
+        assert_eq!(mode("home/.ssh/known_hosts"), 0o644);
+        assert!(permissions.drift("home", &files)?.is_empty());
+
+        set_mode(Path::new("home/.ssh/config"), 0o640)?;
+        let expect = vec![Drift {
+            path: "home/.ssh/config".into(),
+            expect: 0o600,
+            found: 0o640,
+        }];
+        assert_eq!(permissions.drift("home", &files)?, expect);
+
+        let result = Permissions::new([(".netrc", "rw-------")]);
+        assert!(matches!(result, Err(Error::InvalidMode { .. })));
+
+        Ok(())
+    }
+
+    #[sealed_test]
+    fn permissions_keep_directories_enterable() -> Result<()> {
+        create_dir_all("home/.ssh/keys")?;
+        create_dir_all("home/.config/app")?;
+        write("home/.ssh/config", "Host *\n")?;
+        write("home/.ssh/keys/id_ed25519", "\n")?;
+        write("home/.config/rc", "\n")?;
+        write("home/.config/app/rc", "\n")?;
+        set_mode(Path::new("home/.config/rc"), 0o644)?;
+
+        let permissions =
+            Permissions::new([(".ssh", "700"), (".ssh/*", "600"), (".config/*/", "750")])?;
+        let files = vec![
+            PathBuf::from(".ssh/config"),
+            ".ssh/keys/id_ed25519".into(),
+            ".config/rc".into(),
+            ".config/app/rc".into(),
+        ];
+
+        permissions.apply("home", &files)?;
+        assert_eq!(mode("home/.ssh"), 0o700);
+        assert_eq!(mode("home/.ssh/config"), 0o600);
+        assert_eq!(mode("home/.ssh/keys"), 0o700);
+        assert_eq!(mode("home/.config/app"), 0o750);
+        assert_eq!(mode("home/.config/rc"), 0o644);
+        assert!(permissions.drift("home", &files)?.is_empty());
+
+        Ok(())
+    }
+}
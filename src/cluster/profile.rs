@@ -0,0 +1,247 @@
+// SPDX-FileCopyrightText: 2025 Jason Pena <jasonpena@awkless.com>
+// SPDX-License-Identifier: MIT
+
+//! Conditional include profiles.
+//!
+//! The `include` setting of a cluster definition is the same on every
+//! machine. However, a laptop may need battery scripts that a server does
+//! not, and a server has no use for GUI configurations. Thus, cluster
+//! definitions can declare named profiles under `[settings.profiles.<name>]`
+//! that adjust the default sparsity rules on the machines they apply to.
+//!
+//! # Selection
+//!
+//! By default, profiles are selected automatically by matching their
+//! conditions against facts about the current machine: `hostname`, `os`,
+//! `arch`, and `env` variables. Every condition of a profile must match for
+//! it to be selected, and profiles without conditions are never selected
+//! automatically. Alternatively, profiles can be selected explicitly by name,
+//! which skips automatic selection altogether.
+//!
+//! # Rules
+//!
+//! Selected profiles add their `include` rules to the default rules of the
+//! cluster. Their `exclude` rules then remove matching default rules, or are
+//! added as negated rules otherwise. Negated rules only work if sparse
+//! checkout does not operate in cone mode.
+
+use crate::{
+    cluster::template::Facts,
+    config::{ClusterProfile, ClusterSettings},
+};
+
+use glob::Pattern;
+use std::env;
+
+/// How include profiles are selected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProfileSelection {
+    /// Select profiles whose conditions match facts about a machine.
+    Detect(Facts),
+
+    /// Select profiles by name.
+    Named(Vec<String>),
+}
+
+impl ProfileSelection {
+    /// Select profiles that match the current machine.
+    pub fn detect() -> Self {
+        Self::Detect(Facts::detect())
+    }
+
+    /// Select profiles of cluster settings.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::Unknown`] if a profile selected by name does not
+    ///   exist.
+    pub fn select<'a>(
+        &self,
+        settings: &'a ClusterSettings,
+    ) -> Result<Vec<(&'a str, &'a ClusterProfile)>> {
+        let profiles = settings.profiles.iter().flatten();
+        match self {
+            Self::Detect(facts) => Ok(profiles
+                .filter(|(_, profile)| is_match(profile, facts))
+                .map(|(name, profile)| (name.as_str(), profile))
+                .collect()),
+            Self::Named(names) => names
+                .iter()
+                .map(|name| {
+                    settings
+                        .profiles
+                        .as_ref()
+                        .and_then(|profiles| profiles.get_key_value(name))
+                        .map(|(name, profile)| (name.as_str(), profile))
+                        .ok_or_else(|| Error::Unknown { name: name.clone() })
+                })
+                .collect(),
+        }
+    }
+
+    /// Determine default sparsity rules of cluster settings.
+    ///
+    /// Returns [`None`] if cluster settings have no default rules, and no
+    /// selected profile adjusts them.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::Unknown`] if a profile selected by name does not
+    ///   exist.
+    pub fn default_rules(&self, settings: &ClusterSettings) -> Result<Option<Vec<String>>> {
+        let selected = self.select(settings)?;
+        if settings.include.is_none() && selected.is_empty() {
+            return Ok(None);
+        }
+
+        let mut rules = settings.include.clone().unwrap_or_default();
+        for (_, profile) in &selected {
+            for rule in profile.include.iter().flatten() {
+                if !rules.contains(rule) {
+                    rules.push(rule.clone());
+                }
+            }
+        }
+
+        for (_, profile) in &selected {
+            for rule in profile.exclude.iter().flatten() {
+                if rules.contains(rule) {
+                    rules.retain(|current| current != rule);
+                } else {
+                    rules.push(format!("!{rule}"));
+                }
+            }
+        }
+
+        Ok(Some(rules))
+    }
+}
+
+fn is_match(profile: &ClusterProfile, facts: &Facts) -> bool {
+    let conditions = [
+        (&profile.hostname, &facts.hostname),
+        (&profile.os, &facts.os),
+        (&profile.arch, &facts.arch),
+    ];
+
+    let has_conditions = conditions.iter().any(|(pattern, _)| pattern.is_some())
+        || profile.env.as_ref().is_some_and(|env| !env.is_empty());
+    if !has_conditions {
+        return false;
+    }
+
+    conditions.iter().all(|(pattern, value)| {
+        pattern
+            .as_ref()
+            .is_none_or(|pattern| glob_matches(pattern, value))
+    }) && profile
+        .env
+        .iter()
+        .flatten()
+        .all(|(key, pattern)| env::var(key).is_ok_and(|value| glob_matches(pattern, &value)))
+}
+
+fn glob_matches(pattern: &str, value: &str) -> bool {
+    // INVARIANT: Invalid patterns can still match literally.
+    Pattern::new(pattern)
+        .map(|glob| glob.matches(value))
+        .unwrap_or(pattern == value)
+}
+
+/// Include profile error types.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Profile selected by name does not exist.
+    #[error("cluster has no profile named {name:?}")]
+    Unknown { name: String },
+}
+
+/// Friendly result alias :3
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ClusterDefinition;
+    use pretty_assertions::assert_eq;
+    use sealed_test::prelude::*;
+
+    fn definition() -> ClusterDefinition {
+        r#"
+            [settings]
+            description = "blah"
+            work_tree_alias = "/home/blah"
+            include = ["/.bashrc", "/.config/"]
+
+            [settings.remote]
+            url = "https://blah.org/foo.git"
+
+            [settings.profiles.laptop]
+            hostname = "thinkpad-*"
+            include = ["/.local/bin/battery"]
+
+            [settings.profiles.server]
+            os = "linux"
+            env = { DISPLAY = "" }
+            exclude = ["/.config/", "/.xinitrc"]
+
+            [settings.profiles.manual]
+            include = ["/.manual"]
+        "#
+        .parse()
+        .unwrap()
+    }
+
+    fn facts(hostname: &str) -> Facts {
+        Facts {
+            hostname: hostname.into(),
+            os: "linux".into(),
+            arch: "x86_64".into(),
+        }
+    }
+
+    #[sealed_test(env = [("DISPLAY", "")])]
+    fn profile_selection_detect() -> Result<()> {
+        let definition = definition();
+        let settings = &definition.settings;
+
+        let result = ProfileSelection::Detect(facts("thinkpad-x1")).default_rules(settings)?;
+        let expect = vec![
+            "/.bashrc".to_string(),
+            "/.local/bin/battery".into(),
+            "!/.xinitrc".into(),
+        ];
+        assert_eq!(result, Some(expect));
+
+        let result = ProfileSelection::Detect(facts("desktop")).select(settings)?;
+        let names = result.into_iter().map(|(name, _)| name).collect::<Vec<_>>();
+        assert_eq!(names, vec!["server"]);
+
+        Ok(())
+    }
+
+    #[sealed_test(env = [("DISPLAY", ":0")])]
+    fn profile_selection_named() -> Result<()> {
+        let definition = definition();
+        let settings = &definition.settings;
+
+        let result = ProfileSelection::Detect(facts("desktop")).default_rules(settings)?;
+        assert_eq!(result, settings.include.clone());
+
+        let selection = ProfileSelection::Named(vec!["manual".into()]);
+        let expect = vec![
+            "/.bashrc".to_string(),
+            "/.config/".into(),
+            "/.manual".into(),
+        ];
+        assert_eq!(selection.default_rules(settings)?, Some(expect));
+
+        let selection = ProfileSelection::Named(vec!["nope".into()]);
+        assert!(matches!(
+            selection.select(settings),
+            Err(Error::Unknown { .. })
+        ));
+
+        Ok(())
+    }
+}
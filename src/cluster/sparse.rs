@@ -57,7 +57,8 @@
 //! the user full access to the sparsity rule pattern set to make it easier to
 //! deploy any component of a cluster. Obviously, some user's may take issue
 //! with Oxidot's deployment feature being based on a deprecated feature. So, we
-//! give them a choice between the two modes for sparse checkout.
+//! give them a choice between the two modes for sparse checkout through the
+//! `sparse_mode` setting of [`crate::config::UserConfig`].
 //!
 //! # See Also
 //!
@@ -180,6 +181,19 @@ pub fn path_matches(&self, work_tree_alias: &WorkTreeAlias, path: impl AsRef<Pat
         self.matcher
             .path_matches(work_tree_alias, path, self.current_rules().unwrap())
     }
+
+    /// Match file path to one sparsity rule relative to a work tree alias.
+    ///
+    /// Unlike [`SparsityDrafter::path_matches`], the sparsity rule does not
+    /// need to be in the current rule set.
+    pub fn rule_matches(
+        &self,
+        work_tree_alias: &WorkTreeAlias,
+        path: impl AsRef<Path>,
+        rule: impl Into<String>,
+    ) -> bool {
+        self.matcher.path_matches(work_tree_alias, path, [rule])
+    }
 }
 
 /// Sparsity rule editor.
@@ -188,6 +202,8 @@ pub fn path_matches(&self, work_tree_alias: &WorkTreeAlias, path: impl AsRef<Pat
 ///
 /// - No duplicate sparsity rules.
 /// - Rule insertion does not overwrite existing rules.
+/// - Negated sparsity rules are written after every other rule, because later
+///   rules take precedence.
 #[derive(Debug, Default, Clone, PartialEq, Eq)]
 pub struct SparsityEdit {
     rules: HashSet<String>,
@@ -249,8 +265,9 @@ fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
             return write!(fmt, "");
         }
 
+        // INVARIANT: Negated rules come last, or they never exclude anything.
         let mut rules: Vec<_> = self.rules.iter().collect();
-        rules.sort();
+        rules.sort_by_key(|rule| (rule.starts_with('!'), *rule));
 
         let mut out = String::new();
         for rule in rules {
@@ -407,21 +424,21 @@ fn sparsity_edit_rule_insertion() {
         editor.insert_rule("/**/bin");
         let result = editor.to_string();
         let expect = indoc! {r#"
-            !*.aux
             /**/bin
             /.vim/
+            !*.aux
         "#};
         assert_eq!(result, expect);
 
         editor.insert_rules(["cluster.toml", "/.ssh", "/*"]);
         let result = editor.to_string();
         let expect = indoc! {r#"
-            !*.aux
             /*
             /**/bin
             /.ssh
             /.vim/
             cluster.toml
+            !*.aux
         "#};
         assert_eq!(result, expect);
 
@@ -448,9 +465,9 @@ fn sparsity_edit_rule_removal() {
         editor.remove_rule("/.ssh");
         let result = editor.to_string();
         let expect = indoc! {r#"
-            !*.aux
             /*
             /.vim/
+            !*.aux
         "#};
         assert_eq!(result, expect);
     }
@@ -0,0 +1,415 @@
+// SPDX-FileCopyrightText: 2025 Jason Pena <jasonpena@awkless.com>
+// SPDX-License-Identifier: MIT
+
+//! Templating of tracked files.
+//!
+//! Dotfiles tend to differ only slightly between machines, e.g., the email in
+//! `.gitconfig` on a work machine versus a personal one. Instead of keeping
+//! near duplicate clusters around, tracked files can be templates that get
+//! rendered when they are deployed. The raw template stays in the cluster,
+//! while the rendered result goes to the work tree alias.
+//!
+//! # Templates
+//!
+//! Paths to render are listed in the `templates` setting of the cluster
+//! definition. Entries use gitattributes syntax relative to the top-level of
+//! the cluster. Placeholders in templates take the form `{{ name }}`, and
+//! are replaced by the value of the variable with that name. Text between
+//! braces that is not a valid variable name is left alone. Placeholders
+//! cannot span more than one line.
+//!
+//! # Variables
+//!
+//! Variables are resolved in the following order:
+//!
+//! 1. Variables in the `[settings.vars]` table of the cluster definition.
+//! 2. Facts about the current machine: `hostname`, `os`, and `arch`.
+//! 3. Environment variables through `env.NAME`, e.g., `{{ env.EMAIL }}`.
+//!
+//! Rendering fails if a placeholder names a variable that does not exist.
+//!
+//! # Drift
+//!
+//! Templates hook into Git as a clean and smudge filter driver, see
+//! [`crate::cluster::filter`]. Cleaning maps rendered lines back to the
+//! template lines they came from, such that Git compares the rendered file in
+//! the work tree alias against the rendered template. Thus, status only
+//! reports drift if the rendered result was actually changed. Changed lines
+//! are committed as is.
+
+use crate::{cluster::filter::staged_content, config::ClusterDefinition};
+
+use git2::Repository;
+use std::{
+    collections::BTreeMap,
+    env,
+    path::{Path, PathBuf},
+};
+
+/// Facts about the current machine.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct Facts {
+    /// Host name of machine.
+    pub hostname: String,
+
+    /// Operating system, e.g., "linux" or "macos".
+    pub os: String,
+
+    /// CPU architecture, e.g., "x86_64" or "aarch64".
+    pub arch: String,
+}
+
+impl Facts {
+    /// Detect facts about the current machine.
+    pub fn detect() -> Self {
+        Self {
+            hostname: hostname().unwrap_or_default(),
+            os: env::consts::OS.into(),
+            arch: env::consts::ARCH.into(),
+        }
+    }
+}
+
+/// Variables that templates are rendered with.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct TemplateVars {
+    vars: BTreeMap<String, String>,
+    facts: Facts,
+}
+
+impl TemplateVars {
+    /// Construct new template variables with facts about the current machine.
+    pub fn new(vars: impl IntoIterator<Item = (String, String)>) -> Self {
+        Self::with_facts(vars, Facts::detect())
+    }
+
+    /// Construct new template variables with given facts.
+    pub fn with_facts(vars: impl IntoIterator<Item = (String, String)>, facts: Facts) -> Self {
+        Self {
+            vars: vars.into_iter().collect(),
+            facts,
+        }
+    }
+
+    /// Look up value of variable by name.
+    pub fn lookup(&self, name: &str) -> Option<String> {
+        if let Some(value) = self.vars.get(name) {
+            return Some(value.clone());
+        }
+
+        match name {
+            "hostname" => Some(self.facts.hostname.clone()),
+            "os" => Some(self.facts.os.clone()),
+            "arch" => Some(self.facts.arch.clone()),
+            name => name.strip_prefix("env.").and_then(|key| env::var(key).ok()),
+        }
+    }
+
+    /// Render template.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::Undefined`] if a placeholder names a variable that
+    ///   does not exist.
+    pub fn render(&self, template: &str) -> Result<String> {
+        let mut result = String::with_capacity(template.len());
+        let mut rest = template;
+        while let Some(start) = rest.find("{{") {
+            let after = &rest[start + 2..];
+            let Some(end) = after.find("}}") else {
+                break;
+            };
+
+            result.push_str(&rest[..start]);
+            let name = after[..end].trim();
+            if !is_variable_name(name) || after[..end].contains('\n') {
+                result.push_str("{{");
+                rest = after;
+                continue;
+            }
+
+            let value = self
+                .lookup(name)
+                .ok_or_else(|| Error::Undefined { name: name.into() })?;
+            result.push_str(&value);
+            rest = &after[end + 2..];
+        }
+        result.push_str(rest);
+
+        Ok(result)
+    }
+
+    /// Recover template from rendered content.
+    ///
+    /// Returns `template` itself if rendering it gives `rendered`. Otherwise,
+    /// lines of `rendered` are aligned with rendered lines of `template`, and
+    /// lines aligned with a template line that has a placeholder are replaced
+    /// by that template line. Every other line is kept as is.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::Undefined`] if a placeholder names a variable that
+    ///   does not exist.
+    pub fn clean(&self, rendered: &str, template: &str) -> Result<String> {
+        if self.render(template)? == rendered {
+            return Ok(template.into());
+        }
+
+        // INVARIANT: Placeholders never span lines, so lines render independently.
+        let template = template.lines().collect::<Vec<_>>();
+        let outputs = template
+            .iter()
+            .map(|line| self.render(line))
+            .collect::<Result<Vec<_>>>()?;
+        let lines = rendered.split_inclusive('\n').collect::<Vec<_>>();
+        let contents = lines
+            .iter()
+            .map(|line| line.trim_end_matches(['\n', '\r']))
+            .collect::<Vec<_>>();
+
+        let mut result = String::with_capacity(rendered.len());
+        for ((line, content), aligned) in lines
+            .iter()
+            .zip(&contents)
+            .zip(align_lines(&outputs, &contents))
+        {
+            let ending = &line[content.len()..];
+            match aligned {
+                Some(index) if outputs[index] != template[index] => {
+                    result.push_str(template[index]);
+                }
+                _ => result.push_str(content),
+            }
+            result.push_str(ending);
+        }
+
+        Ok(result)
+    }
+}
+
+/// Clean and smudge filter driver for templates of a cluster.
+///
+/// Invoked by Git through `oxidot filter` for every templated path.
+#[derive(Debug)]
+pub struct TemplateFilter {
+    gitdir: PathBuf,
+}
+
+impl TemplateFilter {
+    /// Construct new filter driver for cluster at gitdir.
+    pub fn new(gitdir: impl Into<PathBuf>) -> Self {
+        Self {
+            gitdir: gitdir.into(),
+        }
+    }
+
+    /// Recover template of tracked file at path from rendered content.
+    ///
+    /// Path is relative to top-level of cluster. The template currently
+    /// staged for that path is used to map rendered lines back. Content is
+    /// left alone if nothing is staged yet, or content is not text.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::Git2`] if staged content or cluster definition cannot
+    ///   be looked up.
+    /// - Return [`Error::Config`] if cluster definition cannot be parsed.
+    /// - Return [`Error::Undefined`] if staged template uses a variable that
+    ///   does not exist.
+    pub fn clean(&self, path: impl AsRef<Path>, content: &[u8]) -> Result<Vec<u8>> {
+        let Ok(rendered) = std::str::from_utf8(content) else {
+            return Ok(content.to_vec());
+        };
+
+        let Some(staged) = staged_content(&self.gitdir, path)? else {
+            return Ok(content.to_vec());
+        };
+
+        let Ok(template) = String::from_utf8(staged) else {
+            return Ok(content.to_vec());
+        };
+
+        Ok(self.vars()?.clean(rendered, &template)?.into_bytes())
+    }
+
+    /// Render template of tracked file.
+    ///
+    /// Content is left alone if it is not text.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::Git2`] if cluster definition cannot be looked up.
+    /// - Return [`Error::Config`] if cluster definition cannot be parsed.
+    /// - Return [`Error::Undefined`] if template uses a variable that does
+    ///   not exist.
+    pub fn smudge(&self, content: &[u8]) -> Result<Vec<u8>> {
+        let Ok(template) = std::str::from_utf8(content) else {
+            return Ok(content.to_vec());
+        };
+
+        Ok(self.vars()?.render(template)?.into_bytes())
+    }
+
+    fn vars(&self) -> Result<TemplateVars> {
+        let repository = Repository::open_bare(&self.gitdir)?;
+        let tree = repository.head()?.peel_to_tree()?;
+        let entry = tree.get_path(Path::new("cluster.toml"))?;
+        let blob = repository.find_blob(entry.id())?;
+        let definition: ClusterDefinition = String::from_utf8_lossy(blob.content()).parse()?;
+
+        Ok(TemplateVars::new(
+            definition.settings.vars.unwrap_or_default(),
+        ))
+    }
+}
+
+fn is_variable_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|ch| ch.is_ascii_alphabetic() || ch == '_')
+        && chars.all(|ch| ch.is_ascii_alphanumeric() || "_.-".contains(ch))
+}
+
+/// Align lines of rendered content with rendered lines of template.
+///
+/// Finds the longest common subsequence of lines, such that lines the user
+/// added or removed do not shift the alignment of the remaining lines. Returns
+/// index of aligned template line for each line of rendered content.
+fn align_lines(template: &[String], rendered: &[&str]) -> Vec<Option<usize>> {
+    let (rows, cols) = (template.len(), rendered.len());
+    let mut lengths = vec![vec![0usize; cols + 1]; rows + 1];
+    for row in (0..rows).rev() {
+        for col in (0..cols).rev() {
+            lengths[row][col] = if template[row] == rendered[col] {
+                lengths[row + 1][col + 1] + 1
+            } else {
+                lengths[row + 1][col].max(lengths[row][col + 1])
+            };
+        }
+    }
+
+    let mut aligned = vec![None; cols];
+    let (mut row, mut col) = (0, 0);
+    while row < rows && col < cols {
+        if template[row] == rendered[col] {
+            aligned[col] = Some(row);
+            row += 1;
+            col += 1;
+        } else if lengths[row + 1][col] >= lengths[row][col + 1] {
+            row += 1;
+        } else {
+            col += 1;
+        }
+    }
+
+    aligned
+}
+
+fn hostname() -> Option<String> {
+    gethostname::gethostname().into_string().ok()
+}
+
+/// Templating error types.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Placeholder names a variable that does not exist.
+    #[error("template uses undefined variable {name:?}")]
+    Undefined { name: String },
+
+    /// Cluster definition parsing fails.
+    #[error(transparent)]
+    Config(#[from] crate::config::Error),
+
+    /// Operations from libgit2 fail.
+    #[error(transparent)]
+    Git2(#[from] git2::Error),
+}
+
+/// Friendly result alias :3
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+    use pretty_assertions::assert_eq;
+    use sealed_test::prelude::*;
+
+    fn vars() -> TemplateVars {
+        let facts = Facts {
+            hostname: "box".into(),
+            os: "linux".into(),
+            arch: "x86_64".into(),
+        };
+        TemplateVars::with_facts([("email".into(), "me@blah.org".into())], facts)
+    }
+
+    #[sealed_test(env = [("EDITOR", "vim")])]
+    fn template_vars_render() -> Result<()> {
+        let template = indoc! {"
+            [user]
+                email = {{ email }}
+            [core]
+                editor = {{env.EDITOR}}
+            # {{ hostname }} on {{ os }}-{{ arch }}, {{ not a variable }}
+        "};
+
+        let result = vars().render(template)?;
+        let expect = indoc! {"
+            [user]
+                email = me@blah.org
+            [core]
+                editor = vim
+            # box on linux-x86_64, {{ not a variable }}
+        "};
+        assert_eq!(result, expect);
+
+        let result = vars().render("{{ nope }}");
+        assert!(matches!(result, Err(Error::Undefined { name }) if name == "nope"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn template_vars_clean() -> Result<()> {
+        let template = indoc! {"
+            [user]
+                name = Blah
+                email = {{ email }}
+        "};
+
+        // Unchanged rendered content recovers template as is.
+        let rendered = vars().render(template)?;
+        assert_eq!(vars().clean(&rendered, template)?, template);
+
+        // Changed lines are kept, untouched rendered lines map back.
+        let rendered = rendered.replace("Blah", "Foo");
+        let expect = indoc! {"
+            [user]
+                name = Foo
+                email = {{ email }}
+        "};
+        assert_eq!(vars().clean(&rendered, template)?, expect);
+
+        // Only lines aligned with a placeholder map back, even if lines were
+        // added before them.
+        let rendered = indoc! {"
+            # Reach me at me@blah.org
+            [user]
+                email = me@blah.org
+                name = Blah
+                email = me@blah.org
+        "};
+        let expect = indoc! {"
+            # Reach me at me@blah.org
+            [user]
+                email = me@blah.org
+                name = Blah
+                email = {{ email }}
+        "};
+        assert_eq!(vars().clean(rendered, template)?, expect);
+
+        Ok(())
+    }
+}
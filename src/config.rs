@@ -6,9 +6,29 @@
 //! Specify the layout for configuration files that Oxidot uses to simplify
 //! the process of serialization and deserialization. File I/O is left to the
 //! caller to figure out.
+//!
+//! Oxidot uses two kinds of configuration files: cluster definitions that are
+//! tracked by each cluster, and a user configuration file that applies to
+//! Oxidot as a whole on the current machine.
+//!
+//! # See Also
+//!
+//! 1. [`edit`]
+//! 2. [`lint`]
+//! 3. [`migrate`]
+//! 4. [`schema`]
+
+pub mod edit;
+pub mod lint;
+pub mod migrate;
+pub mod schema;
+
+use crate::config::migrate::SCHEMA_VERSION;
 
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::{
+    collections::BTreeMap,
     ffi::OsString,
     fmt::{Display, Error as FmtError, Formatter, Result as FmtResult},
     path::{Path, PathBuf},
@@ -29,8 +49,21 @@
 /// be configured. The dependencies section lists all dependencies that should
 /// be deployed along with the cluster itself. In other words, clusters can
 /// list other clusters as dependencies.
-#[derive(Default, Debug, PartialEq, Eq, Clone, Hash, Deserialize, Serialize)]
+///
+/// Unknown fields are rejected, such that typos do not silently do nothing.
+/// Valid definitions that are still likely mistakes are caught by [`lint`].
+///
+/// # Schema Version
+///
+/// Every definition records the version of its layout. Older layouts are
+/// upgraded on parse, and newer layouts are rejected, see [`migrate`].
+#[derive(Debug, PartialEq, Eq, Clone, Hash, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct ClusterDefinition {
+    /// Version of definition layout, where zero means unversioned.
+    #[serde(default)]
+    pub schema_version: u32,
+
     /// Settings for the cluster.
     pub settings: ClusterSettings,
 
@@ -39,19 +72,30 @@ pub struct ClusterDefinition {
     pub dependencies: Option<Vec<ClusterDependency>>,
 }
 
+impl Default for ClusterDefinition {
+    fn default() -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            settings: ClusterSettings::default(),
+            dependencies: None,
+        }
+    }
+}
+
 impl FromStr for ClusterDefinition {
     type Err = Error;
 
     fn from_str(data: &str) -> Result<Self, Self::Err> {
+        // INVARIANT: Older layouts are upgraded before deserialization.
+        let upgraded = migrate::upgrade_layout(data)?;
+        let data = upgraded.as_deref().unwrap_or(data);
         let mut definition: ClusterDefinition =
             toml::de::from_str(data).map_err(Error::Deserialize)?;
+        definition.schema_version = SCHEMA_VERSION;
 
         // INVARIANT: Perform shell expansion on work tree alias field.
-        definition.settings.work_tree_alias = WorkTreeAlias::new(
-            shellexpand::full(definition.settings.work_tree_alias.to_string().as_str())
-                .map_err(Error::ShellExpansion)?
-                .into_owned(),
-        );
+        definition.settings.work_tree_alias =
+            WorkTreeAlias::expand(definition.settings.work_tree_alias.raw())?;
 
         Ok(definition)
     }
@@ -70,7 +114,8 @@ fn fmt(&self, fmt: &mut Formatter<'_>) -> FmtResult {
 /// Cluster configuration settings.
 ///
 /// Standard settings to use for any given cluster.
-#[derive(Default, Debug, PartialEq, Eq, Clone, Hash, Deserialize, Serialize)]
+#[derive(Default, Debug, PartialEq, Eq, Clone, Hash, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct ClusterSettings {
     /// Brief description of what the cluster contains.
     pub description: String,
@@ -78,17 +123,88 @@ pub struct ClusterSettings {
     /// Remote repository settings for cloning.
     pub remote: ClusterRemote,
 
+    /// Extra remotes to add to cloned cluster by name, e.g., "upstream".
+    pub remotes: Option<BTreeMap<String, String>>,
+
     /// Work tree alias to use for deployment.
     pub work_tree_alias: WorkTreeAlias,
 
     /// Default listing of file content to deploy to work tree alias.
     pub include: Option<Vec<String>>,
+
+    /// Maximum number of untracked files git-add may stage without asking.
+    pub add_threshold: Option<usize>,
+
+    /// Paths in work tree alias that must never be staged.
+    pub deny: Option<Vec<String>>,
+
+    /// Paths in cluster that are never scanned for secrets.
+    pub allow_secrets: Option<Vec<String>>,
+
+    /// Paths in cluster whose content is encrypted when committed.
+    pub encrypt: Option<Vec<String>>,
+
+    /// Paths in cluster that are templates rendered on deployment.
+    pub templates: Option<Vec<String>>,
+
+    /// Variables that templates are rendered with.
+    pub vars: Option<BTreeMap<String, String>>,
+
+    /// Conditional listings of file content to deploy by name.
+    pub profiles: Option<BTreeMap<String, ClusterProfile>>,
+
+    /// Listings of file content that are deployed together by name.
+    pub groups: Option<BTreeMap<String, Vec<String>>>,
+
+    /// Commands to run before tracked files are deployed.
+    pub pre_deploy: Option<Vec<String>>,
+
+    /// Commands to run after tracked files are deployed.
+    pub post_deploy: Option<Vec<String>>,
+
+    /// Commands to run before tracked files are undeployed.
+    pub pre_undeploy: Option<Vec<String>>,
+
+    /// Commands to run after tracked files are undeployed.
+    pub post_undeploy: Option<Vec<String>>,
+
+    /// Octal modes that deployed paths must have by pattern.
+    pub permissions: Option<BTreeMap<String, String>>,
+}
+
+/// Cluster include profile.
+///
+/// Adjusts the default listing of file content to deploy on machines that
+/// match every condition of the profile. Conditions are glob patterns. A
+/// profile without conditions is only ever selected by name.
+#[derive(Default, Debug, PartialEq, Eq, Clone, Hash, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ClusterProfile {
+    /// Pattern that host name must match.
+    pub hostname: Option<String>,
+
+    /// Pattern that operating system must match, e.g., "linux" or "macos".
+    pub os: Option<String>,
+
+    /// Pattern that CPU architecture must match, e.g., "x86_64".
+    pub arch: Option<String>,
+
+    /// Environment variables that must be set, with patterns their values
+    /// must match.
+    pub env: Option<BTreeMap<String, String>>,
+
+    /// Additional listing of file content to deploy.
+    pub include: Option<Vec<String>>,
+
+    /// Listing of file content to not deploy.
+    pub exclude: Option<Vec<String>>,
 }
 
 /// Cluster dependency listing.
 ///
 /// List of other clusters to use as dependencies for given cluster.
-#[derive(Default, Debug, PartialEq, Eq, Clone, Hash, Deserialize, Serialize)]
+#[derive(Default, Debug, PartialEq, Eq, Clone, Hash, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct ClusterDependency {
     /// Name of the cluster dependency.
     pub name: String,
@@ -103,33 +219,81 @@ pub struct ClusterDependency {
 /// Cluster remote settings.
 ///
 /// Where and how to clone a given cluster target.
-#[derive(Default, Debug, PartialEq, Eq, Clone, Hash, Deserialize, Serialize)]
+#[derive(Default, Debug, PartialEq, Eq, Clone, Hash, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct ClusterRemote {
     /// Remote URL to use for cloning.
     pub url: String,
 
     /// Branch to use for cloning.
     pub branch: Option<String>,
+
+    /// Mirror URLs to fall back to in order if cloning from URL fails.
+    pub mirrors: Option<Vec<String>>,
+}
+
+impl ClusterRemote {
+    /// Iterate over URL followed by mirror URLs in order of preference.
+    pub fn urls(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.url.as_str()).chain(self.mirrors.iter().flatten().map(String::as_str))
+    }
 }
 
 /// Path acting as the work tree alias for given cluster.
-#[derive(Default, Debug, PartialEq, Eq, Clone, Hash, Deserialize, Serialize)]
-pub struct WorkTreeAlias(PathBuf);
+///
+/// Keeps the path as written, e.g., `$HOME/dotfiles`, apart from the path
+/// that shell expansion produces on the current machine. Only the raw form is
+/// serialized, such that definitions stay portable across users and machines,
+/// while deployment works with the expanded form.
+#[derive(Default, Debug, PartialEq, Eq, Clone, Hash, Deserialize, Serialize, JsonSchema)]
+#[serde(from = "String", into = "String")]
+pub struct WorkTreeAlias {
+    raw: String,
+    expanded: PathBuf,
+}
 
 impl WorkTreeAlias {
-    /// Construct new work tree alias.
+    /// Construct new work tree alias without shell expansion.
+    ///
+    /// Raw and expanded forms are both the given path.
     pub fn new(path: impl Into<PathBuf>) -> Self {
-        Self(path.into())
+        let expanded = path.into();
+        Self {
+            raw: expanded.to_string_lossy().into_owned(),
+            expanded,
+        }
+    }
+
+    /// Construct new work tree alias by performing shell expansion on raw
+    /// path.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::ShellExpansion`] if raw path cannot be expanded.
+    pub fn expand(raw: impl Into<String>) -> Result<Self> {
+        let raw = raw.into();
+        let expanded = PathBuf::from(
+            shellexpand::full(raw.as_str())
+                .map_err(Error::ShellExpansion)?
+                .into_owned(),
+        );
+
+        Ok(Self { raw, expanded })
     }
 
-    /// Convert work tree alias to [`OsString`].
+    /// Path as written before shell expansion.
+    pub fn raw(&self) -> &str {
+        self.raw.as_str()
+    }
+
+    /// Convert expanded work tree alias to [`OsString`].
     pub fn to_os_string(&self) -> OsString {
-        OsString::from(self.0.to_string_lossy().into_owned())
+        self.expanded.clone().into_os_string()
     }
 
-    /// Treat work tree alias as [`Path`] slice.
+    /// Treat expanded work tree alias as [`Path`] slice.
     pub fn as_path(&self) -> &Path {
-        self.0.as_path()
+        self.expanded.as_path()
     }
 }
 
@@ -163,6 +327,144 @@ fn from(path: PathBuf) -> Self {
     }
 }
 
+impl From<WorkTreeAlias> for String {
+    fn from(work_tree_alias: WorkTreeAlias) -> Self {
+        work_tree_alias.raw
+    }
+}
+
+/// User configuration layout.
+///
+/// Machine-wide settings for Oxidot itself. By default the user configuration
+/// file is found at `$XDG_CONFIG_HOME/oxidot/config.toml`. Every field is
+/// optional, such that an empty or missing user configuration file simply
+/// means that Oxidot should stick to its defaults.
+#[derive(Default, Debug, PartialEq, Eq, Clone, Hash, Deserialize, Serialize)]
+pub struct UserConfig {
+    /// Path to cluster store.
+    pub store: Option<PathBuf>,
+
+    /// Default work tree alias to use for new clusters.
+    pub work_tree_alias: Option<WorkTreeAlias>,
+
+    /// Maximum number of clusters to clone at the same time.
+    pub jobs: Option<usize>,
+
+    /// Sparse checkout mode to use for new clusters.
+    pub sparse_mode: Option<SparseMode>,
+
+    /// Pager to use for output of Git commands.
+    pub pager: Option<String>,
+
+    /// Format of Oxidot's own output.
+    pub output: Option<OutputFormat>,
+
+    /// Prefixes of remote URLs to rewrite before cloning.
+    pub url_rewrites: Option<UrlRewrites>,
+}
+
+impl FromStr for UserConfig {
+    type Err = Error;
+
+    fn from_str(data: &str) -> Result<Self, Self::Err> {
+        let mut config: UserConfig = toml::de::from_str(data).map_err(Error::Deserialize)?;
+
+        // INVARIANT: Perform shell expansion on cluster store path.
+        if let Some(store) = &config.store {
+            config.store = Some(PathBuf::from(
+                shellexpand::full(store.to_string_lossy().as_ref())
+                    .map_err(Error::ShellExpansion)?
+                    .into_owned(),
+            ));
+        }
+
+        // INVARIANT: Perform shell expansion on default work tree alias.
+        if let Some(work_tree_alias) = &config.work_tree_alias {
+            config.work_tree_alias = Some(WorkTreeAlias::expand(work_tree_alias.raw())?);
+        }
+
+        Ok(config)
+    }
+}
+
+impl Display for UserConfig {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> FmtResult {
+        fmt.write_str(
+            toml::ser::to_string_pretty(self)
+                .map_err(Error::Serialize)?
+                .as_str(),
+        )
+    }
+}
+
+/// Rewrite rules for remote URLs.
+///
+/// Maps URL prefixes to replacements, e.g., `"https://github.com/"` to
+/// `"git@github.com:"`, much like `url.<base>.insteadOf` in Git. Rules only
+/// apply to the URLs that are actually cloned from, such that cluster
+/// definitions keep their original URLs. If multiple prefixes match, then the
+/// longest one wins.
+#[derive(Default, Debug, PartialEq, Eq, Clone, Hash, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct UrlRewrites(BTreeMap<String, String>);
+
+impl UrlRewrites {
+    /// Rewrite URL by longest matching prefix, if any.
+    pub fn apply(&self, url: &str) -> String {
+        self.0
+            .iter()
+            .filter(|(prefix, _)| url.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or_else(
+                || url.into(),
+                |(prefix, replacement)| format!("{replacement}{}", &url[prefix.len()..]),
+            )
+    }
+}
+
+impl<K: Into<String>, V: Into<String>> FromIterator<(K, V)> for UrlRewrites {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        Self(
+            iter.into_iter()
+                .map(|(prefix, replacement)| (prefix.into(), replacement.into()))
+                .collect(),
+        )
+    }
+}
+
+/// Sparse checkout mode.
+///
+/// Selects `core.sparseCheckoutCone` for new clusters. Git matches rules in
+/// cone mode by directory, which is much faster than non-cone mode, but warns
+/// about rules that are not plain directories. Oxidot passes sparsity rules on
+/// to Git as they are in either mode. See [`crate::cluster::sparse`] for more
+/// details.
+#[derive(Default, Debug, PartialEq, Eq, Clone, Copy, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SparseMode {
+    /// Match sparsity rules as gitignore patterns.
+    #[default]
+    NoCone,
+
+    /// Match sparsity rules by directory.
+    Cone,
+}
+
+/// Format of Oxidot's own output.
+#[derive(Default, Debug, PartialEq, Eq, Clone, Copy, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputFormat {
+    /// Short single line messages.
+    #[default]
+    Compact,
+
+    /// Multi-line messages that are easier on the eyes.
+    Pretty,
+
+    /// Single line messages with every piece of information available.
+    Full,
+}
+
 /// Configuration error types.
 #[derive(Clone, Debug, thiserror::Error)]
 pub enum Error {
@@ -177,6 +479,10 @@ pub enum Error {
     /// Failed to perform shell expansion on configuration.
     #[error(transparent)]
     ShellExpansion(#[from] shellexpand::LookupError<std::env::VarError>),
+
+    /// Schema version of cluster definition is not supported.
+    #[error(transparent)]
+    Migrate(#[from] migrate::Error),
 }
 
 impl From<Error> for FmtError {
@@ -202,10 +508,34 @@ fn deserialize_cluster_definition() -> anyhow::Result<()> {
             description = "blah blah blah"
             work_tree_alias = "$BLAH"
             include = ["file1", "file2", "file3"]
+            add_threshold = 50
+            deny = [".cache/"]
+            allow_secrets = [".netrc"]
+            encrypt = [".ssh/config"]
+            templates = [".gitconfig"]
+            post_deploy = ["fc-cache -f"]
+            pre_undeploy = ["tmux kill-server", "true"]
+
+            [settings.vars]
+            email = "blah@blah.org"
+
+            [settings.profiles.laptop]
+            hostname = "thinkpad-*"
+            include = ["battery"]
+
+            [settings.groups]
+            vim = ["/.vim/", "/.vimrc"]
+
+            [settings.permissions]
+            ".ssh/*" = "600"
 
             [settings.remote]
             url = "https://blah.org/foo.git"
             branch = "alternate"
+            mirrors = ["https://mirror.org/foo.git"]
+
+            [settings.remotes]
+            upstream = "https://blah.org/upstream/foo.git"
 
             [[dependency]]
             name = "bar"
@@ -218,47 +548,174 @@ fn deserialize_cluster_definition() -> anyhow::Result<()> {
         .parse()?;
 
         let expect = ClusterDefinition {
+            schema_version: SCHEMA_VERSION,
             settings: ClusterSettings {
                 description: "blah blah blah".into(),
                 remote: ClusterRemote {
                     url: "https://blah.org/foo.git".into(),
                     branch: Some("alternate".into()),
+                    mirrors: Some(vec!["https://mirror.org/foo.git".into()]),
                 },
-                work_tree_alias: WorkTreeAlias::new("/home/blah/blah"),
+                remotes: Some(BTreeMap::from([(
+                    "upstream".into(),
+                    "https://blah.org/upstream/foo.git".into(),
+                )])),
+                work_tree_alias: WorkTreeAlias::expand("$BLAH")?,
                 include: Some(vec!["file1".into(), "file2".into(), "file3".into()]),
+                add_threshold: Some(50),
+                deny: Some(vec![".cache/".into()]),
+                allow_secrets: Some(vec![".netrc".into()]),
+                encrypt: Some(vec![".ssh/config".into()]),
+                templates: Some(vec![".gitconfig".into()]),
+                vars: Some(BTreeMap::from([("email".into(), "blah@blah.org".into())])),
+                profiles: Some(BTreeMap::from([(
+                    "laptop".into(),
+                    ClusterProfile {
+                        hostname: Some("thinkpad-*".into()),
+                        include: Some(vec!["battery".into()]),
+                        ..Default::default()
+                    },
+                )])),
+                groups: Some(BTreeMap::from([(
+                    "vim".into(),
+                    vec!["/.vim/".into(), "/.vimrc".into()],
+                )])),
+                pre_deploy: None,
+                post_deploy: Some(vec!["fc-cache -f".into()]),
+                pre_undeploy: Some(vec!["tmux kill-server".into(), "true".into()]),
+                post_undeploy: None,
+                permissions: Some(BTreeMap::from([(".ssh/*".into(), "600".into())])),
             },
             dependencies: Some(vec![ClusterDependency {
                 name: "bar".into(),
                 remote: ClusterRemote {
                     url: "https://blah.org/bar.git".into(),
                     branch: Some("blah".into()),
+                    mirrors: None,
                 },
                 include: Some(vec!["file1".into(), "file2".into(), "file3".into()]),
             }]),
         };
 
+        assert_eq!(result, expect);
+        assert_eq!(
+            result.settings.remote.urls().collect::<Vec<_>>(),
+            ["https://blah.org/foo.git", "https://mirror.org/foo.git"]
+        );
+        assert_eq!(
+            result.settings.work_tree_alias.as_path(),
+            Path::new("/home/blah/blah")
+        );
+
+        Ok(())
+    }
+
+    #[sealed_test(env = [("BLAH", "/home/blah")])]
+    fn cluster_definition_keeps_raw_work_tree_alias() -> anyhow::Result<()> {
+        let result: ClusterDefinition = indoc! {r#"
+            [settings]
+            description = "blah"
+            work_tree_alias = "$BLAH/dotfiles"
+
+            [settings.remote]
+            url = "https://blah.org/foo.git"
+        "#}
+        .parse()?;
+
+        let work_tree_alias = &result.settings.work_tree_alias;
+        assert_eq!(work_tree_alias.raw(), "$BLAH/dotfiles");
+        assert_eq!(work_tree_alias.as_path(), Path::new("/home/blah/dotfiles"));
+        assert!(result
+            .to_string()
+            .contains(r#"work_tree_alias = "$BLAH/dotfiles""#));
+
+        Ok(())
+    }
+
+    #[sealed_test(env = [("BLAH", "/home/blah")])]
+    fn deserialize_user_config() -> anyhow::Result<()> {
+        let result: UserConfig = r#"
+            store = "$BLAH/store"
+            work_tree_alias = "$BLAH"
+            jobs = 4
+            sparse_mode = "cone"
+            pager = "less -R"
+            output = "pretty"
+
+            [url_rewrites]
+            "https://github.com/" = "git@github.com:"
+            "https://github.com/blah/" = "https://mirror.org/blah/"
+        "#
+        .parse()?;
+
+        let expect = UserConfig {
+            store: Some("/home/blah/store".into()),
+            work_tree_alias: Some(WorkTreeAlias::expand("$BLAH")?),
+            jobs: Some(4),
+            sparse_mode: Some(SparseMode::Cone),
+            pager: Some("less -R".into()),
+            output: Some(OutputFormat::Pretty),
+            url_rewrites: Some(UrlRewrites::from_iter([
+                ("https://github.com/", "git@github.com:"),
+                ("https://github.com/blah/", "https://mirror.org/blah/"),
+            ])),
+        };
         assert_eq!(result, expect);
 
+        let rewrites = result.url_rewrites.unwrap_or_default();
+        assert_eq!(
+            rewrites.apply("https://github.com/foo/bar.git"),
+            "git@github.com:foo/bar.git"
+        );
+        assert_eq!(
+            rewrites.apply("https://github.com/blah/bar.git"),
+            "https://mirror.org/blah/bar.git"
+        );
+        assert_eq!(
+            rewrites.apply("https://blah.org/bar.git"),
+            "https://blah.org/bar.git"
+        );
+
+        let result: UserConfig = "".parse()?;
+        assert_eq!(result, UserConfig::default());
+
         Ok(())
     }
 
     #[test]
     fn serialize_cluster_definition() {
         let result = ClusterDefinition {
+            schema_version: SCHEMA_VERSION,
             settings: ClusterSettings {
                 description: "blah blah blah".into(),
                 remote: ClusterRemote {
                     url: "https://blah.org/foo.git".into(),
                     branch: None,
+                    mirrors: None,
                 },
+                remotes: None,
                 work_tree_alias: WorkTreeAlias::new("/home/blah/blah"),
                 include: Some(vec!["file1".into(), "file2".into(), "file3".into()]),
+                add_threshold: None,
+                deny: None,
+                allow_secrets: None,
+                encrypt: None,
+                templates: None,
+                vars: None,
+                profiles: None,
+                groups: None,
+                pre_deploy: None,
+                post_deploy: None,
+                pre_undeploy: None,
+                post_undeploy: None,
+                permissions: None,
             },
             dependencies: Some(vec![ClusterDependency {
                 name: "bar".into(),
                 remote: ClusterRemote {
                     url: "https://blah.org/bar.git".into(),
                     branch: None,
+                    mirrors: None,
                 },
                 include: Some(vec!["file1".into(), "file2".into(), "file3".into()]),
             }]),
@@ -266,6 +723,8 @@ fn serialize_cluster_definition() {
         .to_string();
 
         let expect = indoc! {r#"
+            schema_version = 1
+
             [settings]
             description = "blah blah blah"
             work_tree_alias = "/home/blah/blah"
@@ -0,0 +1,442 @@
+// SPDX-FileCopyrightText: 2025 Jason Pena <jasonpena@awkless.com>
+// SPDX-License-Identifier: MIT
+
+//! Format-preserving editing of cluster definitions.
+//!
+//! Cluster definitions are written by hand and committed into their cluster.
+//! Comments, ordering, and formatting that the user chose should survive any
+//! edit that Oxidot makes on their behalf. Thus, [`DefinitionEditor`] edits
+//! the TOML document itself, and only validates it as a [`ClusterDefinition`]
+//! once editing is done.
+//!
+//! # Keys
+//!
+//! Keys are dotted TOML keys, e.g., `settings.remote.url`, or
+//! `settings.permissions.".ssh/*"` for keys that need quoting. Keys that do
+//! not start with `settings` or `dependency` are relative to the `settings`
+//! table, thus `remote.url` is the same as `settings.remote.url`.
+//!
+//! # Values
+//!
+//! Values are parsed as TOML values, e.g., `["/.bashrc", "/.profile"]` or
+//! `50`. Anything that is not a valid TOML value is taken as a plain string,
+//! such that `my shell setup` needs no extra quoting.
+
+use crate::config::{ClusterDefinition, ClusterDependency};
+
+use std::{
+    fmt::{Display, Formatter, Result as FmtResult},
+    str::FromStr,
+};
+use toml_edit::{value, Array, ArrayOfTables, DocumentMut, Item, Key, Table, TableLike, Value};
+
+/// Editor for cluster definition documents.
+#[derive(Debug, Clone)]
+pub struct DefinitionEditor {
+    document: DocumentMut,
+}
+
+impl DefinitionEditor {
+    /// Get value at key.
+    ///
+    /// Strings are returned without quotes. Every other value, including
+    /// tables, is returned as TOML.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::InvalidKey`] if key cannot be parsed.
+    /// - Return [`Error::KeyNotFound`] if key does not exist.
+    pub fn get(&self, key: &str) -> Result<String> {
+        let mut item = self.document.as_item();
+        for part in parse_key(key)? {
+            item = item
+                .get(part.get())
+                .ok_or_else(|| Error::KeyNotFound { key: key.into() })?;
+        }
+
+        match item {
+            Item::None => Err(Error::KeyNotFound { key: key.into() }),
+            Item::Value(Value::String(string)) => Ok(string.value().clone()),
+            Item::Value(value) => {
+                let mut value = value.clone();
+                value.decor_mut().clear();
+                Ok(value.to_string())
+            }
+            Item::Table(table) => Ok(table.to_string()),
+            Item::ArrayOfTables(tables) => Ok(tables.to_string()),
+        }
+    }
+
+    /// Set value at key.
+    ///
+    /// Missing tables along the way are created. Comments around a replaced
+    /// value are kept.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::InvalidKey`] if key cannot be parsed.
+    /// - Return [`Error::NotTable`] if part of key is not a table.
+    pub fn set(&mut self, key: &str, data: &str) -> Result<()> {
+        let mut new = data.parse::<Value>().unwrap_or_else(|_| Value::from(data));
+        new.decor_mut().clear();
+
+        let parts = parse_key(key)?;
+        let (last, parents) = parts
+            .split_last()
+            .ok_or_else(|| Error::InvalidKey { key: key.into() })?;
+        let mut table: &mut dyn TableLike = self.document.as_table_mut();
+        for part in parents {
+            let mut implicit = Table::new();
+            implicit.set_implicit(true);
+            table = table
+                .entry(part.get())
+                .or_insert(Item::Table(implicit))
+                .as_table_like_mut()
+                .ok_or_else(|| Error::NotTable { key: key.into() })?;
+        }
+
+        match table.get_mut(last.get()) {
+            Some(Item::Value(existing)) => {
+                let decor = existing.decor().clone();
+                *existing = new;
+                *existing.decor_mut() = decor;
+            }
+            _ => {
+                table.insert(last.get(), value(new));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Remove key along with its value.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::InvalidKey`] if key cannot be parsed.
+    /// - Return [`Error::KeyNotFound`] if key does not exist.
+    pub fn unset(&mut self, key: &str) -> Result<()> {
+        let parts = parse_key(key)?;
+        let (last, parents) = parts
+            .split_last()
+            .ok_or_else(|| Error::InvalidKey { key: key.into() })?;
+        let mut table: &mut dyn TableLike = self.document.as_table_mut();
+        for part in parents {
+            table = table
+                .get_mut(part.get())
+                .and_then(Item::as_table_like_mut)
+                .ok_or_else(|| Error::KeyNotFound { key: key.into() })?;
+        }
+
+        table
+            .remove(last.get())
+            .map(|_| ())
+            .ok_or_else(|| Error::KeyNotFound { key: key.into() })
+    }
+
+    /// Add cluster dependency.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::DuplicateDependency`] if a dependency with the same
+    ///   name already exists.
+    /// - Return [`Error::NotTable`] if dependency listing is malformed.
+    pub fn add_dependency(&mut self, dependency: &ClusterDependency) -> Result<()> {
+        let dependencies = self
+            .document
+            .entry("dependency")
+            .or_insert(Item::ArrayOfTables(ArrayOfTables::new()))
+            .as_array_of_tables_mut()
+            .ok_or_else(|| Error::NotTable {
+                key: "dependency".into(),
+            })?;
+        if find_dependency(dependencies, &dependency.name).is_some() {
+            return Err(Error::DuplicateDependency {
+                name: dependency.name.clone(),
+            });
+        }
+
+        let mut table = Table::new();
+        table.insert("name", value(dependency.name.as_str()));
+        if let Some(include) = &dependency.include {
+            table.insert("include", value(include.iter().collect::<Array>()));
+        }
+
+        let mut remote = Table::new();
+        remote.insert("url", value(dependency.remote.url.as_str()));
+        if let Some(branch) = &dependency.remote.branch {
+            remote.insert("branch", value(branch.as_str()));
+        }
+        if let Some(mirrors) = &dependency.remote.mirrors {
+            remote.insert("mirrors", value(mirrors.iter().collect::<Array>()));
+        }
+        table.insert("remote", Item::Table(remote));
+        dependencies.push(table);
+
+        Ok(())
+    }
+
+    /// Remove cluster dependency by name.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::DependencyNotFound`] if no dependency has that name.
+    pub fn remove_dependency(&mut self, name: &str) -> Result<()> {
+        let not_found = || Error::DependencyNotFound { name: name.into() };
+        let dependencies = self
+            .document
+            .get_mut("dependency")
+            .and_then(Item::as_array_of_tables_mut)
+            .ok_or_else(not_found)?;
+        let index = find_dependency(dependencies, name).ok_or_else(not_found)?;
+        dependencies.remove(index);
+
+        if dependencies.is_empty() {
+            self.document.remove("dependency");
+        }
+
+        Ok(())
+    }
+
+    /// Validate document as cluster definition.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::Invalid`] if document is not a valid cluster
+    ///   definition.
+    pub fn definition(&self) -> Result<ClusterDefinition> {
+        Ok(self.to_string().parse()?)
+    }
+}
+
+impl FromStr for DefinitionEditor {
+    type Err = Error;
+
+    fn from_str(data: &str) -> Result<Self, Self::Err> {
+        Ok(Self {
+            document: data.parse()?,
+        })
+    }
+}
+
+impl Display for DefinitionEditor {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> FmtResult {
+        write!(fmt, "{}", self.document)
+    }
+}
+
+/// Normalize key such that it is relative to top-level of cluster
+/// definition.
+///
+/// # Errors
+///
+/// - Return [`Error::InvalidKey`] if key cannot be parsed.
+pub fn normalize_key(key: &str) -> Result<String> {
+    Ok(parse_key(key)?
+        .iter()
+        .map(|part| part.display_repr().into_owned())
+        .collect::<Vec<_>>()
+        .join("."))
+}
+
+/// Prefix of lines that explain a problem of a cluster definition being
+/// edited by hand.
+pub const PROBLEM_PREFIX: &str = "# oxidot: ";
+
+/// Explain problem of cluster definition at the top of its contents.
+///
+/// Returns contents as is if there is no problem. Explanation is marked by
+/// [`PROBLEM_PREFIX`], such that [`strip_problem`] can remove it again.
+pub fn annotate_problem(problem: Option<&str>, contents: &str) -> String {
+    let Some(problem) = problem else {
+        return contents.into();
+    };
+
+    let mut annotated =
+        format!("{PROBLEM_PREFIX}cluster definition is invalid, fix it or empty file to abort\n");
+    for line in problem.lines() {
+        annotated.push_str(&format!("{PROBLEM_PREFIX}{line}\n"));
+    }
+    annotated.push_str(contents);
+
+    annotated
+}
+
+/// Remove explanation of problem that [`annotate_problem`] added.
+pub fn strip_problem(contents: &str) -> String {
+    contents
+        .split_inclusive('\n')
+        .skip_while(|line| line.starts_with(PROBLEM_PREFIX.trim_end()))
+        .collect()
+}
+
+fn parse_key(key: &str) -> Result<Vec<Key>> {
+    let mut parts = Key::parse(key).map_err(|_| Error::InvalidKey { key: key.into() })?;
+
+    // INVARIANT: Keys outside of known top-level tables belong to settings.
+    if !matches!(
+        parts.first().map(Key::get),
+        Some("settings") | Some("dependency")
+    ) {
+        parts.insert(0, Key::new("settings"));
+    }
+
+    Ok(parts)
+}
+
+fn find_dependency(dependencies: &ArrayOfTables, name: &str) -> Option<usize> {
+    dependencies
+        .iter()
+        .position(|table| table.get("name").and_then(Item::as_str) == Some(name))
+}
+
+/// Cluster definition editing error types.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Document is not valid TOML.
+    #[error(transparent)]
+    Parse(#[from] toml_edit::TomlError),
+
+    /// Key is not a valid dotted TOML key.
+    #[error("invalid key {key:?}")]
+    InvalidKey { key: String },
+
+    /// Key does not exist in document.
+    #[error("key {key:?} is not set")]
+    KeyNotFound { key: String },
+
+    /// Part of key is a value instead of a table.
+    #[error("key {key:?} goes through a value that is not a table")]
+    NotTable { key: String },
+
+    /// Dependency with same name already exists.
+    #[error("dependency {name:?} already exists")]
+    DuplicateDependency { name: String },
+
+    /// Dependency does not exist.
+    #[error("dependency {name:?} does not exist")]
+    DependencyNotFound { name: String },
+
+    /// Document changed while it was edited by hand.
+    #[error("cluster definition changed while it was edited, edit aborted")]
+    Changed,
+
+    /// Edited document is not a valid cluster definition.
+    #[error(transparent)]
+    Invalid(#[from] crate::config::Error),
+}
+
+/// Friendly result alias :3
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ClusterRemote;
+    use indoc::indoc;
+    use pretty_assertions::assert_eq;
+
+    const DEFINITION: &str = indoc! {r#"
+        # Shell configuration.
+        [settings]
+        description = "blah" # keep me
+        work_tree_alias = "$HOME"
+        include = ["/.bashrc"]
+
+        [settings.remote]
+        url = "https://blah.org/foo.git"
+    "#};
+
+    #[test]
+    fn definition_editor_set_get_unset() -> anyhow::Result<()> {
+        let mut editor: DefinitionEditor = DEFINITION.parse()?;
+        editor.set("description", "my shell setup")?;
+        editor.set("settings.include", r#"["/.bashrc", "/.profile"]"#)?;
+        editor.set("vars.email", "me@blah.org")?;
+        editor.unset("remote.url")?;
+
+        assert_eq!(editor.get("description")?, "my shell setup");
+        assert_eq!(editor.get("include")?, r#"["/.bashrc", "/.profile"]"#);
+        assert!(matches!(
+            editor.get("remote.url"),
+            Err(Error::KeyNotFound { .. })
+        ));
+        assert!(matches!(
+            editor.set("description.nope", "x"),
+            Err(Error::NotTable { .. })
+        ));
+
+        let expect = indoc! {r#"
+            # Shell configuration.
+            [settings]
+            description = "my shell setup" # keep me
+            work_tree_alias = "$HOME"
+            include = ["/.bashrc", "/.profile"]
+
+            [settings.remote]
+
+            [settings.vars]
+            email = "me@blah.org"
+        "#};
+        assert_eq!(editor.to_string(), expect);
+        assert_eq!(normalize_key("remote.url")?, "settings.remote.url");
+
+        Ok(())
+    }
+
+    #[test]
+    fn definition_editor_dependencies() -> anyhow::Result<()> {
+        let mut editor: DefinitionEditor = DEFINITION.parse()?;
+        let dependency = ClusterDependency {
+            name: "vim".into(),
+            remote: ClusterRemote {
+                url: "https://blah.org/vim.git".into(),
+                branch: Some("main".into()),
+                mirrors: Some(vec!["https://mirror.org/vim.git".into()]),
+            },
+            include: None,
+        };
+        editor.add_dependency(&dependency)?;
+        assert!(matches!(
+            editor.add_dependency(&dependency),
+            Err(Error::DuplicateDependency { .. })
+        ));
+        assert_eq!(
+            editor.definition()?.dependencies,
+            Some(vec![dependency.clone()])
+        );
+
+        editor.remove_dependency("vim")?;
+        assert_eq!(editor.to_string(), DEFINITION);
+        assert!(matches!(
+            editor.remove_dependency("vim"),
+            Err(Error::DependencyNotFound { .. })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn annotate_and_strip_problem() {
+        let contents = indoc! {r#"
+            # my cluster
+            [settings]
+        "#};
+        assert_eq!(annotate_problem(None, contents), contents);
+
+        let result = annotate_problem(Some("missing field `remote`\nat line 1"), contents);
+        let expect = indoc! {r#"
+            # oxidot: cluster definition is invalid, fix it or empty file to abort
+            # oxidot: missing field `remote`
+            # oxidot: at line 1
+            # my cluster
+            [settings]
+        "#};
+        assert_eq!(result, expect);
+        assert_eq!(strip_problem(&result), contents);
+
+        // Emptied explanation lines are stripped, comments of user are kept.
+        let result = strip_problem("# oxidot:\n# oxidot: fine\n# my cluster\n");
+        assert_eq!(result, "# my cluster\n");
+    }
+}
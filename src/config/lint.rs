@@ -0,0 +1,328 @@
+// SPDX-FileCopyrightText: 2025 Jason Pena <jasonpena@awkless.com>
+// SPDX-License-Identifier: MIT
+
+//! Lints for cluster definitions.
+//!
+//! Parsing a cluster definition already rejects malformed layouts, e.g.,
+//! unknown fields or values of the wrong type, along with the line and column
+//! at fault. However, plenty of valid cluster definitions are still likely
+//! mistakes, like a remote URL that `oxidot init` left as a placeholder. These
+//! are reported as [`Lint`]s, which point to the line and column of the
+//! offending value in the source of the cluster definition.
+
+use crate::config::{ClusterDefinition, ClusterRemote};
+
+use std::{
+    collections::HashSet,
+    fmt::{Display, Formatter, Result as FmtResult},
+    path::PathBuf,
+};
+use toml_edit::Document;
+
+/// Placeholder that `oxidot init` uses for missing description.
+pub const PLACEHOLDER_DESCRIPTION: &str = "<put one sentence description here>";
+
+/// Placeholder that `oxidot init` uses for missing remote URL.
+pub const PLACEHOLDER_URL: &str = "<put url to remote here>";
+
+/// Likely mistake in cluster definition.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Lint {
+    /// Dotted key of offending value.
+    pub key: String,
+
+    /// Line of offending value, starting at one.
+    pub line: usize,
+
+    /// Column of offending value, starting at one.
+    pub column: usize,
+
+    /// What is wrong with the value.
+    pub kind: LintKind,
+}
+
+impl Display for Lint {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> FmtResult {
+        write!(
+            fmt,
+            "cluster.toml:{}:{}: {}: {}",
+            self.line, self.column, self.key, self.kind
+        )
+    }
+}
+
+/// Kinds of likely mistakes in cluster definitions.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum LintKind {
+    /// Value was never filled in.
+    Placeholder { value: String },
+
+    /// Remote URL is empty.
+    EmptyUrl,
+
+    /// Work tree alias does not exist on current machine.
+    MissingWorkTreeAlias { path: PathBuf },
+
+    /// Dependency name is listed more than once.
+    DuplicateDependency { name: String },
+
+    /// Cluster lists itself as a dependency.
+    SelfDependency { name: String },
+}
+
+impl Display for LintKind {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Self::Placeholder { value } => write!(fmt, "placeholder {value:?} was never filled in"),
+            Self::EmptyUrl => write!(fmt, "remote URL is empty"),
+            Self::MissingWorkTreeAlias { path } => {
+                write!(fmt, "work tree alias {:?} does not exist", path.display())
+            }
+            Self::DuplicateDependency { name } => {
+                write!(fmt, "dependency {name:?} is listed more than once")
+            }
+            Self::SelfDependency { name } => write!(fmt, "cluster {name:?} depends on itself"),
+        }
+    }
+}
+
+/// Lint source of cluster definition of named cluster.
+///
+/// Lints are ordered by where they appear in the source.
+///
+/// # Errors
+///
+/// - Return [`Error::Invalid`] if source is not a valid cluster definition.
+/// - Return [`Error::Parse`] if source is not valid TOML.
+pub fn lint(source: &str, name: &str) -> Result<Vec<Lint>> {
+    let definition: ClusterDefinition = source.parse()?;
+    let document = Document::parse(source)?;
+    let mut lints = Vec::new();
+    let mut report = |path: &[Segment<'_>], kind: LintKind| {
+        let offset = locate(&document, path).unwrap_or_default();
+        let (line, column) = line_column(source, offset);
+        lints.push(Lint {
+            key: path
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join("."),
+            line,
+            column,
+            kind,
+        });
+    };
+
+    let settings = &definition.settings;
+    if is_placeholder(&settings.description) {
+        report(
+            &[Segment::Key("settings"), Segment::Key("description")],
+            LintKind::Placeholder {
+                value: settings.description.clone(),
+            },
+        );
+    }
+
+    let remote = [Segment::Key("settings"), Segment::Key("remote")];
+    lint_remote(&settings.remote, &remote, &mut report);
+
+    let work_tree_alias = settings.work_tree_alias.as_path();
+    if !work_tree_alias.exists() {
+        report(
+            &[Segment::Key("settings"), Segment::Key("work_tree_alias")],
+            LintKind::MissingWorkTreeAlias {
+                path: work_tree_alias.into(),
+            },
+        );
+    }
+
+    let mut seen = HashSet::new();
+    for (index, dependency) in definition.dependencies.iter().flatten().enumerate() {
+        let key = [
+            Segment::Key("dependency"),
+            Segment::Index(index),
+            Segment::Key("name"),
+        ];
+        if dependency.name == name {
+            report(
+                &key,
+                LintKind::SelfDependency {
+                    name: dependency.name.clone(),
+                },
+            );
+        }
+        if !seen.insert(dependency.name.as_str()) {
+            report(
+                &key,
+                LintKind::DuplicateDependency {
+                    name: dependency.name.clone(),
+                },
+            );
+        }
+
+        let remote = [
+            Segment::Key("dependency"),
+            Segment::Index(index),
+            Segment::Key("remote"),
+        ];
+        lint_remote(&dependency.remote, &remote, &mut report);
+    }
+
+    lints.sort_by_key(|lint| (lint.line, lint.column));
+
+    Ok(lints)
+}
+
+/// Part of path to value in cluster definition.
+#[derive(Debug, Clone, Copy)]
+enum Segment<'a> {
+    Key(&'a str),
+    Index(usize),
+}
+
+impl Display for Segment<'_> {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Self::Key(key) => fmt.write_str(key),
+            Self::Index(index) => write!(fmt, "{index}"),
+        }
+    }
+}
+
+fn lint_remote(
+    remote: &ClusterRemote,
+    path: &[Segment<'_>],
+    report: &mut impl FnMut(&[Segment<'_>], LintKind),
+) {
+    let url = [path, &[Segment::Key("url")]].concat();
+    lint_url(&remote.url, &url, report);
+    for (index, mirror) in remote.mirrors.iter().flatten().enumerate() {
+        let url = [path, &[Segment::Key("mirrors"), Segment::Index(index)]].concat();
+        lint_url(mirror, &url, report);
+    }
+}
+
+fn lint_url(url: &str, path: &[Segment<'_>], report: &mut impl FnMut(&[Segment<'_>], LintKind)) {
+    if url.trim().is_empty() {
+        report(path, LintKind::EmptyUrl);
+    } else if is_placeholder(url) {
+        report(path, LintKind::Placeholder { value: url.into() });
+    }
+}
+
+fn is_placeholder(value: &str) -> bool {
+    value == PLACEHOLDER_DESCRIPTION
+        || value == PLACEHOLDER_URL
+        || (value.starts_with("<put ") && value.ends_with('>'))
+}
+
+/// Find byte offset of value at path, or of closest parent that has one.
+fn locate(document: &Document<&str>, path: &[Segment<'_>]) -> Option<usize> {
+    let mut item = document.as_item();
+    let mut offset = None;
+    for segment in path {
+        let next = match segment {
+            Segment::Key(key) => item.get(*key),
+            Segment::Index(index) => item.get(*index),
+        };
+        let Some(next) = next else {
+            break;
+        };
+        item = next;
+        offset = item.span().map(|span| span.start).or(offset);
+    }
+
+    offset
+}
+
+fn line_column(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset.min(source.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before
+        .rsplit('\n')
+        .next()
+        .map_or(0, |line| line.chars().count())
+        + 1;
+
+    (line, column)
+}
+
+/// Cluster definition lint error types.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Source is not valid TOML.
+    #[error(transparent)]
+    Parse(#[from] toml_edit::TomlError),
+
+    /// Source is not a valid cluster definition.
+    #[error(transparent)]
+    Invalid(#[from] crate::config::Error),
+}
+
+/// Friendly result alias :3
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn lint_reports_mistakes_with_location() -> Result<()> {
+        let source = indoc! {r#"
+            [settings]
+            description = "<put one sentence description here>"
+            work_tree_alias = "/oxidot/does/not/exist"
+
+            [settings.remote]
+            url = ""
+
+            [[dependency]]
+            name = "vim"
+            remote = { url = "https://blah.org/vim.git" }
+
+            [[dependency]]
+            name = "vim"
+            remote = { url = "<put url to remote here>", mirrors = [""] }
+
+            [[dependency]]
+            name = "shell"
+            remote = { url = "https://blah.org/shell.git" }
+        "#};
+
+        let result = lint(source, "shell")?
+            .into_iter()
+            .map(|lint| lint.to_string())
+            .collect::<Vec<_>>();
+        let expect = vec![
+            r#"cluster.toml:2:15: settings.description: placeholder "<put one sentence description here>" was never filled in"#,
+            r#"cluster.toml:3:19: settings.work_tree_alias: work tree alias "/oxidot/does/not/exist" does not exist"#,
+            "cluster.toml:6:7: settings.remote.url: remote URL is empty",
+            r#"cluster.toml:13:8: dependency.1.name: dependency "vim" is listed more than once"#,
+            r#"cluster.toml:14:18: dependency.1.remote.url: placeholder "<put url to remote here>" was never filled in"#,
+            "cluster.toml:14:57: dependency.1.remote.mirrors.0: remote URL is empty",
+            r#"cluster.toml:17:8: dependency.2.name: cluster "shell" depends on itself"#,
+        ];
+        assert_eq!(result, expect);
+
+        Ok(())
+    }
+
+    #[test]
+    fn cluster_definition_rejects_unknown_fields() {
+        let source = indoc! {r#"
+            [settings]
+            description = "blah"
+            work_tree_alias = "/blah"
+            includes = ["/.bashrc"]
+
+            [settings.remote]
+            url = "https://blah.org/foo.git"
+        "#};
+
+        let result = lint(source, "shell").unwrap_err().to_string();
+        assert!(result.contains("line 4, column 1"), "{result}");
+        assert!(result.contains("unknown field `includes`"), "{result}");
+    }
+}
@@ -0,0 +1,189 @@
+// SPDX-FileCopyrightText: 2025 Jason Pena <jasonpena@awkless.com>
+// SPDX-License-Identifier: MIT
+
+//! Schema versions of cluster definitions.
+//!
+//! Cluster definitions are shared between machines that may not run the same
+//! version of Oxidot. Thus, every cluster definition records the version of
+//! its layout in a top-level `schema_version` field. Cluster definitions
+//! without one predate versioning, and are treated as version zero.
+//!
+//! # Migration
+//!
+//! Older layouts are upgraded one version at a time by the migrations listed
+//! in [`MIGRATIONS`], where the migration at index `n` upgrades version `n` to
+//! version `n + 1`. Migrations edit the TOML document itself, such that
+//! comments and formatting survive when an upgraded cluster definition is
+//! written back through `oxidot migrate`. Cluster definitions are always
+//! upgraded in memory when they are parsed.
+//!
+//! Layouts newer than [`SCHEMA_VERSION`] cannot be understood, and are
+//! rejected outright instead of being misread.
+
+use toml_edit::{value, DocumentMut, Item};
+
+/// Schema version of cluster definitions that this version of Oxidot uses.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Name of schema version field in cluster definitions.
+pub const VERSION_KEY: &str = "schema_version";
+
+/// Upgrade of cluster definition document by one schema version.
+pub type Migration = fn(&mut DocumentMut) -> Result<()>;
+
+/// Migrations by the schema version that they upgrade from.
+pub const MIGRATIONS: [Migration; SCHEMA_VERSION as usize] = [unversioned_to_v1];
+
+/// Determine schema version of cluster definition document.
+///
+/// # Errors
+///
+/// - Return [`Error::InvalidVersion`] if schema version is not a
+///   non-negative integer.
+pub fn schema_version(document: &DocumentMut) -> Result<u32> {
+    match document.get(VERSION_KEY) {
+        None => Ok(0),
+        Some(item) => item
+            .as_integer()
+            .and_then(|version| u32::try_from(version).ok())
+            .ok_or_else(|| Error::InvalidVersion {
+                found: item.to_string().trim().into(),
+            }),
+    }
+}
+
+/// Upgrade source of cluster definition to current schema version.
+///
+/// Returns [`None`] if source is already at current schema version, or is not
+/// valid TOML to begin with.
+///
+/// # Errors
+///
+/// - Return [`Error::InvalidVersion`] if schema version is not a
+///   non-negative integer.
+/// - Return [`Error::Unsupported`] if schema version is newer than
+///   [`SCHEMA_VERSION`].
+pub fn migrate(source: &str) -> Result<Option<String>> {
+    upgrade(source, true)
+}
+
+/// Upgrade layout of source in memory without recording new schema version.
+///
+/// Lines only move if a migration moves them, so errors found in result
+/// still point at the right place of source most of the time. Returns
+/// [`None`] if layout did not change.
+pub(crate) fn upgrade_layout(source: &str) -> Result<Option<String>> {
+    upgrade(source, false)
+}
+
+fn upgrade(source: &str, record: bool) -> Result<Option<String>> {
+    // INVARIANT: Leave reporting of malformed TOML to deserialization.
+    let Ok(mut document) = source.parse::<DocumentMut>() else {
+        return Ok(None);
+    };
+
+    let version = schema_version(&document)?;
+    if version > SCHEMA_VERSION {
+        return Err(Error::Unsupported { found: version });
+    }
+    if version == SCHEMA_VERSION {
+        return Ok(None);
+    }
+
+    for (from, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        migration(&mut document)?;
+        if record {
+            set_version(&mut document, from as u32 + 1);
+        }
+    }
+
+    let upgraded = document.to_string();
+    Ok((upgraded != source).then_some(upgraded))
+}
+
+fn set_version(document: &mut DocumentMut, version: u32) {
+    match document.get_mut(VERSION_KEY) {
+        Some(Item::Value(existing)) => {
+            let decor = existing.decor().clone();
+            *existing = i64::from(version).into();
+            *existing.decor_mut() = decor;
+        }
+        _ => {
+            // INVARIANT: Root values always come before tables, so version goes first.
+            document.insert(VERSION_KEY, value(i64::from(version)));
+        }
+    }
+}
+
+/// Layout of version one is the unversioned layout, so only the schema
+/// version itself is added.
+fn unversioned_to_v1(_: &mut DocumentMut) -> Result<()> {
+    Ok(())
+}
+
+/// Schema version error types.
+#[derive(Clone, Debug, thiserror::Error)]
+pub enum Error {
+    /// Schema version is not a non-negative integer.
+    #[error("invalid schema version {found}")]
+    InvalidVersion { found: String },
+
+    /// Schema version is newer than this version of Oxidot supports.
+    #[error(
+        "cluster definition uses schema version {found}, but this oxidot only supports up to version {SCHEMA_VERSION}, please upgrade oxidot"
+    )]
+    Unsupported { found: u32 },
+}
+
+/// Friendly result alias :3
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn migrate_unversioned_keeps_formatting() -> Result<()> {
+        let source = indoc! {r#"
+            # Shell configuration.
+            [settings]
+            description = "blah" # keep me
+            work_tree_alias = "$HOME"
+
+            [settings.remote]
+            url = "https://blah.org/foo.git"
+        "#};
+        let expect = indoc! {r#"
+            schema_version = 1
+            # Shell configuration.
+            [settings]
+            description = "blah" # keep me
+            work_tree_alias = "$HOME"
+
+            [settings.remote]
+            url = "https://blah.org/foo.git"
+        "#};
+
+        let result = migrate(source)?;
+        assert_eq!(result.as_deref(), Some(expect));
+        assert_eq!(migrate(expect)?, None);
+        assert_eq!(upgrade_layout(source)?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn migrate_rejects_newer_versions() {
+        let result = migrate("schema_version = 999\n");
+        assert!(matches!(result, Err(Error::Unsupported { found: 999 })));
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("please upgrade oxidot"));
+
+        let result = migrate("schema_version = \"one\"\n");
+        assert!(matches!(result, Err(Error::InvalidVersion { .. })));
+    }
+}
@@ -0,0 +1,67 @@
+// SPDX-FileCopyrightText: 2025 Jason Pena <jasonpena@awkless.com>
+// SPDX-License-Identifier: MIT
+
+//! JSON Schema of cluster definitions.
+//!
+//! Editors can validate and complete `cluster.toml` files through a JSON
+//! Schema, e.g., through taplo in VS Code or Helix. The schema is generated
+//! from the layout types in [`crate::config`] themselves, so it follows every
+//! serde attribute, and uses their doc comments as descriptions.
+
+use crate::config::ClusterDefinition;
+
+use schemars::{generate::SchemaSettings, transform::transform_subschemas, Schema};
+use serde_json::Value;
+
+/// Generate JSON Schema of cluster definition layout.
+pub fn cluster_definition_schema() -> Schema {
+    // INVARIANT: Schema describes what gets deserialized.
+    let mut schema = SchemaSettings::draft07()
+        .for_deserialize()
+        .with_transform(remove_null)
+        .into_generator()
+        .into_root_schema_for::<ClusterDefinition>();
+    schema.insert("title".into(), "cluster.toml".into());
+
+    schema
+}
+
+/// Remove null from types of optional fields, because TOML has no null.
+fn remove_null(schema: &mut Schema) {
+    if let Some(Value::Array(types)) = schema.get_mut("type") {
+        types.retain(|kind| kind != "null");
+        if types.len() == 1 {
+            let kind = types.remove(0);
+            schema.insert("type".into(), kind);
+        }
+    }
+
+    transform_subschemas(&mut remove_null, schema);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    #[test]
+    fn cluster_definition_schema_follows_layout() {
+        let schema = cluster_definition_schema().to_value();
+        let settings = &schema["definitions"]["ClusterSettings"];
+
+        assert_eq!(schema["additionalProperties"], json!(false));
+        assert_eq!(schema["required"], json!(["settings"]));
+        assert!(schema["properties"]["dependency"].is_object());
+        assert_eq!(settings["additionalProperties"], json!(false));
+        assert_eq!(settings["properties"]["include"]["type"], json!("array"));
+        assert_eq!(
+            schema["definitions"]["WorkTreeAlias"]["type"],
+            json!("string")
+        );
+        assert_eq!(
+            settings["properties"]["include"]["description"],
+            json!("Default listing of file content to deploy to work tree alias.")
+        );
+    }
+}
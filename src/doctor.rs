@@ -0,0 +1,541 @@
+// SPDX-FileCopyrightText: 2025 Jason Pena <jasonpena@awkless.com>
+// SPDX-License-Identifier: MIT
+
+//! Cluster store health checks.
+//!
+//! Examine each cluster entry of a cluster store for problems that would
+//! otherwise cause Oxidot to misbehave, or outright refuse to open the cluster
+//! store. Each problem is reported as a [`Finding`] with a [`Severity`]. Some
+//! findings are considered _safe_ to repair automatically, e.g., resetting a
+//! configuration value that Oxidot itself relies on.
+//!
+//! The cluster store is examined directly through libgit2 instead of going
+//! through [`Store`]. That way, a broken cluster entry can still be diagnosed
+//! even when [`Store::open`] would fail on it.
+//!
+//! [`Store`]: crate::store::Store
+//! [`Store::open`]: crate::store::Store::open
+
+use crate::{
+    cluster::{
+        deploy::REQUIRED_CONFIG,
+        permissions::{deployed_files, Drift, Permissions},
+    },
+    config::{
+        lint::{lint, Lint, LintKind},
+        migrate::{schema_version, SCHEMA_VERSION},
+        ClusterDefinition,
+    },
+};
+
+use git2::{ErrorCode, Repository};
+use std::{
+    collections::HashSet,
+    fmt::{Display, Formatter, Result as FmtResult},
+    fs::{read_to_string, OpenOptions},
+    path::{Path, PathBuf},
+};
+use tracing::{debug, instrument};
+
+/// Examine every cluster entry in a cluster store.
+///
+/// Findings are ordered by cluster entry, and then by the order in which
+/// checks are performed on that cluster entry.
+///
+/// # Errors
+///
+/// - Return [`Error::Glob`] if cluster entry paths cannot be globbed.
+/// - Return [`Error::GlobPattern`] if glob pattern for cluster entry is
+///   invalid.
+#[instrument(skip(store_path), level = "debug")]
+pub fn examine(store_path: impl AsRef<Path>) -> Result<Vec<Finding>> {
+    let pattern = store_path
+        .as_ref()
+        .join("*.git")
+        .to_string_lossy()
+        .into_owned();
+
+    let mut entries = Vec::new();
+    for dir in glob::glob(pattern.as_str())? {
+        // INVARIANT: The name of a cluster is the directory name minus the .git extension.
+        let gitdir = dir?;
+        let name = gitdir.file_stem().unwrap().to_string_lossy().into_owned();
+        entries.push((name, gitdir));
+    }
+    entries.sort();
+
+    let mut findings = Vec::new();
+    let mut definitions = Vec::new();
+    for (name, gitdir) in &entries {
+        debug!("examine cluster {name:?}");
+        if let Some(definition) = examine_cluster(name, gitdir, &mut findings) {
+            definitions.push((name, gitdir, definition));
+        }
+    }
+
+    // INVARIANT: Only clusters that could be opened count as present in the store.
+    let present = definitions
+        .iter()
+        .map(|(name, _, _)| name.as_str())
+        .collect::<HashSet<_>>();
+    for (name, gitdir, definition) in &definitions {
+        for dependency in definition.dependencies.iter().flatten() {
+            if !present.contains(dependency.name.as_str()) {
+                findings.push(Finding::new(
+                    name.as_str(),
+                    gitdir.as_path(),
+                    Problem::MissingDependency {
+                        dependency: dependency.name.clone(),
+                    },
+                ));
+            }
+        }
+    }
+
+    Ok(findings)
+}
+
+fn examine_cluster(
+    name: &str,
+    gitdir: &Path,
+    findings: &mut Vec<Finding>,
+) -> Option<ClusterDefinition> {
+    let mut report = |problem| findings.push(Finding::new(name, gitdir, problem));
+
+    let repository = match Repository::open_bare(gitdir) {
+        Ok(repository) => repository,
+        Err(error) => {
+            report(Problem::NotRepository {
+                reason: error.message().into(),
+            });
+            return None;
+        }
+    };
+
+    let head = match repository.head() {
+        Ok(head) => Some(head),
+        Err(error) if error.code() == ErrorCode::UnbornBranch => {
+            // INVARIANT: An unborn cluster with an origin was never fully cloned.
+            if repository.find_remote("origin").is_ok() {
+                report(Problem::PartialClone);
+                return None;
+            }
+            None
+        }
+        Err(error) => {
+            report(Problem::NotRepository {
+                reason: error.message().into(),
+            });
+            return None;
+        }
+    };
+
+    match repository.config() {
+        Ok(config) => {
+            for (key, expect) in REQUIRED_CONFIG {
+                let found = match config.get_entry(key) {
+                    Ok(entry) => entry.value().map(ToString::to_string),
+                    Err(_) => None,
+                };
+
+                if found.as_deref() != Some(expect) {
+                    report(Problem::ConfigMismatch { key, expect, found });
+                }
+            }
+        }
+        Err(error) => report(Problem::NotRepository {
+            reason: error.message().into(),
+        }),
+    }
+
+    let sparse_path = gitdir.join("info").join("sparse-checkout");
+    match read_to_string(&sparse_path) {
+        Ok(_) => (),
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+            report(Problem::MissingSparseFile)
+        }
+        Err(error) => report(Problem::UnreadableSparseFile {
+            reason: error.to_string(),
+        }),
+    }
+
+    let Some(head) = head else {
+        report(Problem::UnbornHead);
+        return None;
+    };
+
+    if repository.head_detached().unwrap_or(false) {
+        report(Problem::DetachedHead);
+    }
+
+    let definition = head
+        .peel_to_tree()
+        .and_then(|tree| tree.get_path(Path::new("cluster.toml")))
+        .and_then(|entry| entry.to_object(&repository))
+        .and_then(|object| object.peel_to_blob());
+    let source = match definition {
+        Ok(blob) => String::from_utf8_lossy(blob.content()).into_owned(),
+        Err(_) => {
+            report(Problem::MissingDefinition);
+            return None;
+        }
+    };
+
+    let definition = match source.parse::<ClusterDefinition>() {
+        Ok(definition) => definition,
+        Err(error) => {
+            report(Problem::InvalidDefinition {
+                reason: error.to_string(),
+            });
+            return None;
+        }
+    };
+
+    let version = source
+        .parse()
+        .ok()
+        .and_then(|document| schema_version(&document).ok());
+    if let Some(found) = version.filter(|found| *found < SCHEMA_VERSION) {
+        report(Problem::OutdatedSchema { found });
+    }
+
+    // INVARIANT: Missing work tree alias is reported on its own below.
+    match lint(&source, name) {
+        Ok(lints) => lints
+            .into_iter()
+            .filter(|lint| !matches!(lint.kind, LintKind::MissingWorkTreeAlias { .. }))
+            .for_each(|lint| report(Problem::Lint { lint })),
+        Err(error) => debug!("cannot lint {name:?}: {error}"),
+    }
+
+    let work_tree_alias = definition.settings.work_tree_alias.as_path();
+    if !work_tree_alias.is_dir() {
+        report(Problem::MissingWorkTreeAlias {
+            path: work_tree_alias.into(),
+        });
+        return Some(definition);
+    }
+
+    let permissions = match Permissions::new(definition.settings.permissions.iter().flatten()) {
+        Ok(permissions) => permissions,
+        Err(error) => {
+            report(Problem::InvalidDefinition {
+                reason: error.to_string(),
+            });
+            return Some(definition);
+        }
+    };
+
+    let drift = deployed_files(&repository, work_tree_alias)
+        .and_then(|files| permissions.drift(work_tree_alias, &files));
+    match drift {
+        Ok(drift) => drift
+            .into_iter()
+            .for_each(|drift| report(Problem::PermissionDrift { drift })),
+        Err(error) => debug!("cannot check permissions of {name:?}: {error}"),
+    }
+
+    Some(definition)
+}
+
+/// Problem found in a cluster entry of the cluster store.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    /// Name of cluster that has the problem.
+    pub cluster: String,
+
+    /// Path to gitdir of cluster that has the problem.
+    pub gitdir: PathBuf,
+
+    /// The problem itself.
+    pub problem: Problem,
+}
+
+impl Finding {
+    /// Construct new finding.
+    pub fn new(cluster: impl Into<String>, gitdir: impl Into<PathBuf>, problem: Problem) -> Self {
+        Self {
+            cluster: cluster.into(),
+            gitdir: gitdir.into(),
+            problem,
+        }
+    }
+
+    /// How bad the problem is.
+    pub fn severity(&self) -> Severity {
+        self.problem.severity()
+    }
+
+    /// Check if problem can be repaired automatically.
+    pub fn is_fixable(&self) -> bool {
+        self.problem.is_fixable()
+    }
+
+    /// Repair problem if it is safe to do so.
+    ///
+    /// Returns `false` without touching anything when the problem cannot be
+    /// repaired automatically.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::Git2`] if cluster configuration cannot be updated.
+    /// - Return [`Error::Io`] if cluster entry cannot be modified.
+    /// - Return [`Error::Permissions`] if mode of deployed path cannot be set.
+    #[instrument(skip(self), level = "debug")]
+    pub fn repair(&self) -> Result<bool> {
+        match &self.problem {
+            Problem::ConfigMismatch { key, expect, .. } => {
+                let repository = Repository::open_bare(&self.gitdir)?;
+                repository.config()?.set_str(key, expect)?;
+            }
+            Problem::MissingSparseFile => {
+                let sparse_path = self.gitdir.join("info").join("sparse-checkout");
+                mkdirp::mkdirp(self.gitdir.join("info"))?;
+                OpenOptions::new()
+                    .create(true)
+                    .write(true)
+                    .truncate(false)
+                    .open(sparse_path)?;
+            }
+            Problem::PermissionDrift { drift } => drift.repair()?,
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    }
+}
+
+impl Display for Finding {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> FmtResult {
+        write!(fmt, "{}: {}", self.cluster, self.problem)
+    }
+}
+
+/// Kinds of problems a cluster entry can have.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// Cluster entry is not a valid Git repository.
+    NotRepository { reason: String },
+
+    /// Cluster entry was left behind by an interrupted clone.
+    ///
+    /// Never repaired automatically, because an unborn cluster with an origin
+    /// may just as well be a freshly cloned empty repository.
+    PartialClone,
+
+    /// Configuration value differs from what deployment logic expects.
+    ConfigMismatch {
+        key: &'static str,
+        expect: &'static str,
+        found: Option<String>,
+    },
+
+    /// Sparse checkout configuration file does not exist.
+    MissingSparseFile,
+
+    /// Sparse checkout configuration file cannot be read.
+    UnreadableSparseFile { reason: String },
+
+    /// HEAD points to a branch without any commits.
+    UnbornHead,
+
+    /// HEAD points directly to a commit instead of a branch.
+    DetachedHead,
+
+    /// Cluster definition file is not tracked at the top-level.
+    MissingDefinition,
+
+    /// Cluster definition file cannot be parsed.
+    InvalidDefinition { reason: String },
+
+    /// Work tree alias does not exist.
+    MissingWorkTreeAlias { path: PathBuf },
+
+    /// Dependency of cluster is not in the cluster store.
+    MissingDependency { dependency: String },
+
+    /// Deployed path does not have the mode that cluster definition declares.
+    PermissionDrift { drift: Drift },
+
+    /// Cluster definition is valid, but likely has a mistake.
+    Lint { lint: Lint },
+
+    /// Cluster definition uses an older schema version.
+    OutdatedSchema { found: u32 },
+}
+
+impl Problem {
+    /// How bad the problem is.
+    pub fn severity(&self) -> Severity {
+        match self {
+            Self::NotRepository { .. }
+            | Self::UnbornHead
+            | Self::MissingDefinition
+            | Self::InvalidDefinition { .. }
+            | Self::UnreadableSparseFile { .. } => Severity::Error,
+            Self::PartialClone
+            | Self::ConfigMismatch { .. }
+            | Self::MissingSparseFile
+            | Self::DetachedHead
+            | Self::MissingWorkTreeAlias { .. }
+            | Self::MissingDependency { .. }
+            | Self::PermissionDrift { .. }
+            | Self::Lint { .. }
+            | Self::OutdatedSchema { .. } => Severity::Warning,
+        }
+    }
+
+    /// Check if problem can be repaired automatically.
+    pub fn is_fixable(&self) -> bool {
+        matches!(
+            self,
+            Self::ConfigMismatch { .. } | Self::MissingSparseFile | Self::PermissionDrift { .. }
+        )
+    }
+}
+
+impl Display for Problem {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Self::NotRepository { reason } => write!(fmt, "not a valid cluster: {reason}"),
+            Self::PartialClone => write!(
+                fmt,
+                "left behind by an interrupted clone, remove it and clone again"
+            ),
+            Self::ConfigMismatch { key, expect, found } => match found {
+                Some(found) => write!(fmt, "{key} is {found:?}, expected {expect:?}"),
+                None => write!(fmt, "{key} is not set, expected {expect:?}"),
+            },
+            Self::MissingSparseFile => write!(fmt, "sparse checkout file is missing"),
+            Self::UnreadableSparseFile { reason } => {
+                write!(fmt, "sparse checkout file cannot be read: {reason}")
+            }
+            Self::UnbornHead => write!(fmt, "HEAD does not point to any commit"),
+            Self::DetachedHead => write!(fmt, "HEAD is detached"),
+            Self::MissingDefinition => write!(fmt, "cluster.toml is not tracked at top-level"),
+            Self::InvalidDefinition { reason } => write!(fmt, "cluster.toml is invalid: {reason}"),
+            Self::MissingWorkTreeAlias { path } => {
+                write!(fmt, "work tree alias {:?} does not exist", path.display())
+            }
+            Self::MissingDependency { dependency } => {
+                write!(fmt, "dependency {dependency:?} is not in cluster store")
+            }
+            Self::PermissionDrift { drift } => write!(fmt, "{drift}"),
+            Self::Lint { lint } => write!(fmt, "{lint}"),
+            Self::OutdatedSchema { found } => write!(
+                fmt,
+                "cluster.toml uses schema version {found}, run `oxidot migrate` to upgrade to {SCHEMA_VERSION}"
+            ),
+        }
+    }
+}
+
+/// How bad a [`Finding`] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// Cluster works, but not the way Oxidot expects it to.
+    Warning,
+
+    /// Cluster cannot be used until the problem is resolved.
+    Error,
+}
+
+impl Display for Severity {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Self::Warning => fmt.write_str("warning"),
+            Self::Error => fmt.write_str("error"),
+        }
+    }
+}
+
+/// Cluster store health check error types.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Failed to use glob patterns for directory processing.
+    #[error(transparent)]
+    Glob(#[from] glob::GlobError),
+
+    /// Glob pattern was not parsed correctly for some reason.
+    #[error(transparent)]
+    GlobPattern(#[from] glob::PatternError),
+
+    /// Operations from libgit2 fail.
+    #[error(transparent)]
+    Git2(#[from] git2::Error),
+
+    /// Declared modes cannot be applied.
+    #[error(transparent)]
+    Permissions(#[from] crate::cluster::permissions::Error),
+
+    /// Input/Output operations failed.
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// Friendly result alias :3
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use sealed_test::prelude::*;
+
+    fn problems(store_path: &str) -> Result<Vec<(String, Problem)>> {
+        Ok(examine(store_path)?
+            .into_iter()
+            .map(|finding| (finding.cluster, finding.problem))
+            .collect())
+    }
+
+    #[sealed_test]
+    fn examine_and_repair_unborn_cluster() -> Result<()> {
+        Repository::init_bare("store/vim.git")?;
+
+        let expect = vec![
+            (
+                "vim".to_string(),
+                Problem::ConfigMismatch {
+                    key: "status.showUntrackedFiles",
+                    expect: "no",
+                    found: None,
+                },
+            ),
+            (
+                "vim".into(),
+                Problem::ConfigMismatch {
+                    key: "core.sparseCheckout",
+                    expect: "true",
+                    found: None,
+                },
+            ),
+            ("vim".into(), Problem::MissingSparseFile),
+            ("vim".into(), Problem::UnbornHead),
+        ];
+        assert_eq!(problems("store")?, expect);
+
+        for finding in examine("store")? {
+            assert_eq!(finding.repair()?, finding.is_fixable());
+        }
+        let expect = vec![("vim".to_string(), Problem::UnbornHead)];
+        assert_eq!(problems("store")?, expect);
+
+        Ok(())
+    }
+
+    #[sealed_test]
+    fn partial_clone_is_never_removed() -> Result<()> {
+        let repository = Repository::init_bare("store/vim.git")?;
+        repository.remote("origin", "https://blah.org/vim.git")?;
+
+        let findings = examine("store")?;
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].problem, Problem::PartialClone);
+        assert!(!findings[0].is_fixable());
+        assert!(!findings[0].repair()?);
+        assert!(Path::new("store/vim.git/HEAD").exists());
+
+        Ok(())
+    }
+}
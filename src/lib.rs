@@ -61,5 +61,6 @@
 
 pub mod cluster;
 pub mod config;
+pub mod doctor;
 pub mod path;
 pub mod store;
@@ -6,7 +6,10 @@
 //! Determine relevent path information for external files that need to be
 //! interacted with, or managed in some way.
 
-use std::path::PathBuf;
+use std::{env, path::PathBuf};
+
+/// Environment variable that overrides the cluster store path.
+pub const STORE_ENV: &str = "OXIDOT_STORE";
 
 /// Determine absolute path to user's home directory.
 ///
@@ -38,6 +41,56 @@ pub fn default_cluster_store_dir() -> Result<PathBuf> {
         .ok_or(NoWayHome)
 }
 
+/// Determine absolute path to cluster store directory.
+///
+/// Resolves the path to the cluster store in the following order of
+/// precedence, with the first available path being selected:
+///
+/// 1. Path given through the command line, e.g., `--store <path>`.
+/// 2. Path in the `OXIDOT_STORE` environment variable, if not empty.
+/// 3. Path given through the `store` field of the user configuration file.
+/// 4. Path from [`default_cluster_store_dir`].
+///
+/// Does not check if the path returned actually exists.
+///
+/// # Errors
+///
+/// - Return [`NoWayHome`] if default path is needed, but home directory path
+///   cannot be determined.
+pub fn cluster_store_dir(cli: Option<PathBuf>, config: Option<PathBuf>) -> Result<PathBuf> {
+    if let Some(path) = cli {
+        return Ok(path);
+    }
+
+    if let Some(path) = env::var_os(STORE_ENV).filter(|path| !path.is_empty()) {
+        return Ok(PathBuf::from(path));
+    }
+
+    match config {
+        Some(path) => Ok(path),
+        None => default_cluster_store_dir(),
+    }
+}
+
+/// Determine default absolute path to user configuration file.
+///
+/// Uses XDG Base Directory path `$XDG_CONFIG_HOME/oxidot/config.toml` as the
+/// absolute path for the user configuration file. Does not check if the path
+/// returned actually exists.
+///
+/// # Errors
+///
+/// - Return [`NoWayHome`] if home directory path cannot be determined.
+///
+/// # See Also
+///
+/// - [XDG Base Directory](https://wiki.archlinux.org/title/XDG_Base_Directory)
+pub fn user_config_file() -> Result<PathBuf> {
+    dirs::config_dir()
+        .map(|path| path.join("oxidot").join("config.toml"))
+        .ok_or(NoWayHome)
+}
+
 /// No way to determine user's home directory.
 ///
 /// # See Also
@@ -49,3 +102,28 @@ pub fn default_cluster_store_dir() -> Result<PathBuf> {
 
 /// Friendly result alias :3
 pub type Result<T, E = NoWayHome> = std::result::Result<T, E>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use sealed_test::prelude::*;
+
+    #[sealed_test(env = [("OXIDOT_STORE", "/from/env"), ("XDG_DATA_HOME", "/data")])]
+    fn cluster_store_dir_precedence() -> anyhow::Result<()> {
+        let result = cluster_store_dir(Some("/from/cli".into()), Some("/from/config".into()))?;
+        assert_eq!(result, PathBuf::from("/from/cli"));
+
+        let result = cluster_store_dir(None, Some("/from/config".into()))?;
+        assert_eq!(result, PathBuf::from("/from/env"));
+
+        env::remove_var(STORE_ENV);
+        let result = cluster_store_dir(None, Some("/from/config".into()))?;
+        assert_eq!(result, PathBuf::from("/from/config"));
+
+        let result = cluster_store_dir(None, None)?;
+        assert_eq!(result, PathBuf::from("/data/oxidot-store"));
+
+        Ok(())
+    }
+}
@@ -21,50 +21,100 @@
 //! possible to nest clusters inside one another. The closest the user can get
 //! to this is by listing a cluster as a dependency of another cluster via
 //! the cluster definition file.
+//!
+//! The default location can be overridden by the user. See
+//! [`cluster_store_dir`] for how the location of the cluster store is
+//! selected.
+//!
+//! Access to the cluster store is serialized between Oxidot processes through
+//! a lock file at the top-level of the cluster store. See [`lock`] for more
+//! details.
+//!
+//! Clusters are opened lazily. Opening the cluster store only collects the
+//! names of available clusters. The repository and definition of a cluster are
+//! only loaded on first access, and are kept around for any later access.
+//!
+//! [`cluster_store_dir`]: crate::path::cluster_store_dir
+
+pub mod lock;
 
 use crate::{
     cluster::{BranchTarget, Cluster, ClusterAccess, Git2Cluster},
-    config::{ClusterDefinition, ClusterDependency},
+    config::{
+        edit::DefinitionEditor, lint::lint, ClusterDefinition, ClusterDependency, ClusterSettings,
+        UrlRewrites,
+    },
+    store::lock::{LockMode, StoreLock},
 };
 
 use futures::{stream, StreamExt};
 use indicatif::{MultiProgress, ProgressBar};
 use std::{
     collections::{HashMap, HashSet, VecDeque},
+    ffi::{OsStr, OsString},
     fs::remove_dir_all,
     path::{Path, PathBuf},
     sync::{Arc, Mutex, MutexGuard},
 };
-use tracing::{info, instrument, warn};
+use tracing::{debug, error, info, instrument, warn};
 
 /// Cluster store handler.
 #[derive(Debug)]
 pub struct Store {
     state: Arc<Mutex<StoreState>>,
+    lock: StoreLock,
 }
 
 impl Store {
     /// Construct new cluster store manager.
     ///
     /// Will treat target directory as a cluster store. All clusters within
-    /// that path will be opened for management and manipulation.
+    /// that path are registered for management and manipulation, but only get
+    /// opened on first access. The cluster store is locked exclusively until
+    /// the manager is dropped.
     ///
     /// # Errors
     ///
+    /// - Return [`Error::Lock`] if cluster store is locked by another
+    ///   process.
     /// - Return [`Error::Glob`] if cluster entry paths cannot be
     ///   globbed.
     /// - Return [`Error::GlobPattern`] if glob pattern for cluster
     ///   entry is invalid.
-    /// - Return [`Error::Cluster`] if any cluster entry
-    ///   cannot be opened.
     pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::open_with_lock(path, LockMode::Exclusive)
+    }
+
+    /// Construct new cluster store manager for reading only.
+    ///
+    /// Same as [`Store::open`], except that the cluster store is locked in
+    /// shared mode. Other read-only managers can access the cluster store at
+    /// the same time. Operations that add or remove clusters are refused with
+    /// [`Error::ReadOnly`].
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::Lock`] if cluster store is locked exclusively by
+    ///   another process.
+    /// - Return [`Error::Glob`] if cluster entry paths cannot be
+    ///   globbed.
+    /// - Return [`Error::GlobPattern`] if glob pattern for cluster
+    ///   entry is invalid.
+    pub fn open_shared(path: impl AsRef<Path>) -> Result<Self> {
+        Self::open_with_lock(path, LockMode::Shared)
+    }
+
+    fn open_with_lock(path: impl AsRef<Path>, mode: LockMode) -> Result<Self> {
         mkdirp::mkdirp(path.as_ref())?;
+        let lock = StoreLock::acquire(path.as_ref(), mode)?;
 
         let store = Self {
             state: Arc::new(Mutex::new(StoreState::new(path.as_ref(), HashMap::new()))),
+            lock,
         };
 
         let mut state = store.lock_state();
+        state.writable = mode == LockMode::Exclusive;
         let pattern = state
             .store_path
             .join("*.git")
@@ -74,7 +124,7 @@ pub fn open(path: impl AsRef<Path>) -> Result<Self> {
             // INVARIANT: The name of a cluster is the directory name minus the .git extension.
             let path = dir?;
             let name = path.file_stem().unwrap().to_string_lossy().into_owned();
-            state.clusters.insert(name, Git2Cluster::try_open(path)?);
+            state.clusters.insert(name, None);
         }
         drop(state);
 
@@ -82,6 +132,21 @@ pub fn open(path: impl AsRef<Path>) -> Result<Self> {
         Ok(store)
     }
 
+    /// Enable or disable deployment hooks of every cluster in store.
+    ///
+    /// Hooks are enabled by default. Applies to clusters on access, so
+    /// clusters that were already accessed are affected as well.
+    pub fn set_hooks_enabled(&self, enabled: bool) {
+        self.lock_state().hooks = enabled;
+    }
+
+    /// Rewrite URLs of remotes before any cluster is cloned from them.
+    ///
+    /// Cluster definitions keep their original URLs, see [`UrlRewrites`].
+    pub fn set_url_rewrites(&self, url_rewrites: UrlRewrites) {
+        self.lock_state().url_rewrites = url_rewrites;
+    }
+
     /// Initialize new cluster into store.
     ///
     /// Takes a cluster definition to initialize a new cluster with it inside
@@ -89,6 +154,7 @@ pub fn open(path: impl AsRef<Path>) -> Result<Self> {
     ///
     /// # Errors
     ///
+    /// - Return [`Error::ReadOnly`] if cluster store was opened read-only.
     /// - Return [`Error::Cluster`] if cluster cannot be
     ///   initialized.
     pub fn init_cluster(
@@ -96,12 +162,13 @@ pub fn init_cluster(
         name: impl Into<String>,
         definition: ClusterDefinition,
     ) -> Result<()> {
+        self.ensure_writable()?;
         let mut state = self.lock_state();
         let name = name.into();
         let path = state.store_path.join(format!("{}.git", &name));
         state
             .clusters
-            .insert(name, Git2Cluster::try_init(path, definition)?);
+            .insert(name, Some(Git2Cluster::try_init(path, definition)?));
 
         Ok(())
     }
@@ -113,6 +180,7 @@ pub fn init_cluster(
     ///
     /// # Errors
     ///
+    /// - Return [`Error::ReadOnly`] if cluster store was opened read-only.
     /// - Return [`Error::Cluster`] if cluster cannot be
     ///   initialized.
     /// - Return [`Error::ClusterNotFound`] if cluster does not
@@ -120,13 +188,17 @@ pub fn init_cluster(
     /// - Return [`Error::Io`] if cluster could not be deleted
     ///   from cluster store.
     pub fn remove_cluster(&self, name: impl AsRef<str>) -> Result<Cluster> {
+        self.ensure_writable()?;
         let mut state = self.lock_state();
-        let removed = state
-            .clusters
-            .remove(name.as_ref())
-            .ok_or(Error::ClusterNotFound {
-                name: name.as_ref().to_string(),
-            })?;
+        state.load(name.as_ref())?;
+        let removed =
+            state
+                .clusters
+                .remove(name.as_ref())
+                .flatten()
+                .ok_or(Error::ClusterNotFound {
+                    name: name.as_ref().to_string(),
+                })?;
         removed.undeploy_all()?;
         remove_dir_all(state.store_path.join(format!("{}.git", name.as_ref())))?;
 
@@ -135,26 +207,30 @@ pub fn remove_cluster(&self, name: impl AsRef<str>) -> Result<Cluster> {
 
     /// Clone a cluster along with its dependencies.
     ///
-    /// Clones target cluster, and performs dependency resolution by looking
-    /// for missing dependencies. Any missing dependencies are returned to
-    /// allow for dependency resolution if needed.
+    /// Clones target cluster from the first of target URLs, falling back to
+    /// the rest in order as mirrors, and performs dependency resolution by
+    /// looking for missing dependencies. Any missing dependencies are
+    /// returned to allow for dependency resolution if needed.
     ///
     /// # Errors
     ///
+    /// - Return [`Error::ReadOnly`] if cluster store was opened read-only.
     /// - Return [`Error::Cluster`] if cluster cannot be
     ///   initialized.
     pub fn clone_cluster(
         &self,
         name: impl AsRef<str>,
-        url: impl AsRef<str>,
+        urls: impl IntoIterator<Item = impl AsRef<str>>,
         branch: BranchTarget,
     ) -> Result<()> {
+        self.ensure_writable()?;
         let mut state = self.lock_state();
         let path = state.store_path.join(format!("{}.git", name.as_ref()));
         let bar = ProgressBar::no_length();
+        let urls = state.rewrite_urls(urls);
 
-        let cluster = Git2Cluster::try_clone(url.as_ref(), &path, branch, bar.clone())?;
-        state.clusters.insert(name.as_ref().into(), cluster);
+        let cluster = Git2Cluster::try_clone(urls, &path, branch, &state.url_rewrites, bar.clone())?;
+        state.clusters.insert(name.as_ref().into(), Some(cluster));
         bar.finish();
 
         Ok(())
@@ -164,27 +240,38 @@ pub fn clone_cluster(
     ///
     /// Clones all missing dependencies between clusters until all have been
     /// resolved. Each missing cluster dependency is cloned concurrently with
-    /// an interactive progress bar.
+    /// an interactive progress bar, falling back to mirrors of its remote if
+    /// needed. At most `jobs` clusters are cloned at the same time, where
+    /// [`None`] or zero means no limit.
     ///
     /// # Errors
     ///
+    /// - Return [`Error::ReadOnly`] if cluster store was opened read-only.
     /// - Return [`Error::Cluster`] if dependencies could not be cloned.
     /// - Return [`Error::Join`] if threads could not be properly joined.
-    pub async fn resolve_dependencies(&self, start: impl AsRef<str>) -> Result<()> {
+    pub async fn resolve_dependencies(
+        &self,
+        start: impl AsRef<str>,
+        jobs: Option<usize>,
+    ) -> Result<()> {
+        self.ensure_writable()?;
         let mut unresolved_set = {
-            let state = self.lock_state();
-            let cluster = state
-                .clusters
-                .get(start.as_ref())
+            let mut state = self.lock_state();
+            let definition = state
+                .load(start.as_ref())?
                 .ok_or(Error::ClusterNotFound {
                     name: start.as_ref().into(),
-                })?;
+                })?
+                .definition
+                .clone();
 
-            state.find_unresolved_dependencies(&cluster.definition)
+            state.find_unresolved_dependencies(&definition)
         }?;
 
         while !unresolved_set.is_empty() {
-            let mut unresolved = self.resolve_dependeny_set(unresolved_set.clone()).await?;
+            let mut unresolved = self
+                .resolve_dependeny_set(unresolved_set.clone(), jobs)
+                .await?;
             unresolved_set.clear();
             unresolved_set.append(&mut unresolved);
         }
@@ -195,20 +282,33 @@ pub async fn resolve_dependencies(&self, start: impl AsRef<str>) -> Result<()> {
     async fn resolve_dependeny_set(
         &self,
         unresolved: Vec<ClusterDependency>,
+        jobs: Option<usize>,
     ) -> Result<Vec<ClusterDependency>> {
         let multi_bar = MultiProgress::new();
         let mut bars = Vec::new();
 
-        let store_path = {
+        let (store_path, url_rewrites, unresolved) = {
             let state = self.lock_state();
-            state.store_path.clone()
+            let unresolved = unresolved
+                .into_iter()
+                .map(|dep| {
+                    let urls = state.rewrite_urls(dep.remote.urls());
+                    (dep, urls)
+                })
+                .collect::<Vec<_>>();
+            (
+                state.store_path.clone(),
+                state.url_rewrites.clone(),
+                unresolved,
+            )
         };
 
         let results = Arc::new(Mutex::new(Vec::new()));
         stream::iter(unresolved)
-            .for_each_concurrent(None, |dep| {
+            .for_each_concurrent(jobs, |(dep, urls)| {
                 let results = results.clone();
                 let store_path = store_path.clone();
+                let url_rewrites = url_rewrites.clone();
                 let bar = multi_bar.add(ProgressBar::no_length());
                 bars.push(bar.clone());
 
@@ -221,8 +321,13 @@ async fn resolve_dependeny_set(
                             BranchTarget::Default
                         };
 
-                        let cluster =
-                            Git2Cluster::try_clone(&dep.remote.url, &path, dep_branch, bar.clone())?;
+                        let cluster = Git2Cluster::try_clone(
+                            urls,
+                            &path,
+                            dep_branch,
+                            &url_rewrites,
+                            bar.clone(),
+                        )?;
                         bar.finish();
 
                         Ok::<_, Error>((dep.name.clone(), cluster))
@@ -249,7 +354,7 @@ async fn resolve_dependeny_set(
         let mut unresolved = Vec::new();
         for (name, cluster) in clusters {
             unresolved.append(&mut state.find_unresolved_dependencies(&cluster.definition)?);
-            state.clusters.insert(name, cluster);
+            state.clusters.insert(name, Some(cluster));
         }
 
         Ok(unresolved)
@@ -263,18 +368,16 @@ async fn resolve_dependeny_set(
     /// # Errors
     ///
     /// - Return [`Error::ClusterNotFound`] if cluster does not exist.
+    /// - Return [`Error::Cluster`] if cluster cannot be opened.
     /// - Fails if clouser also fails for whatever reason.
     pub fn use_cluster<C, R>(&self, name: impl AsRef<str>, usage: C) -> Result<R>
     where
         C: FnOnce(&Cluster) -> Result<R>,
     {
-        let state = self.lock_state();
-        let cluster = state
-            .clusters
-            .get(name.as_ref())
-            .ok_or(Error::ClusterNotFound {
-                name: name.as_ref().into(),
-            })?;
+        let mut state = self.lock_state();
+        let cluster = state.load(name.as_ref())?.ok_or(Error::ClusterNotFound {
+            name: name.as_ref().into(),
+        })?;
 
         usage(cluster)
     }
@@ -287,30 +390,224 @@ pub fn use_cluster<C, R>(&self, name: impl AsRef<str>, usage: C) -> Result<R>
     /// # Errors
     ///
     /// - Return [`Error::ClusterNotFound`] if cluster does not exist.
+    /// - Return [`Error::Cluster`] if any cluster cannot be opened.
     /// - Fails if clouser also fails for whatever reason.
-    pub fn use_cluster_dependencies<C, R>(
-        &self,
-        start: impl AsRef<str>,
-        usage: C,
-    ) -> Result<Vec<R>>
+    pub fn use_cluster_dependencies<C, R>(&self, start: impl AsRef<str>, usage: C) -> Result<Vec<R>>
     where
         C: FnMut(&Cluster) -> Result<R>,
     {
-        let state = self.lock_state();
-        let cluster = state
-            .clusters
-            .get(start.as_ref())
+        let mut state = self.lock_state();
+        let definition = state
+            .load(start.as_ref())?
             .ok_or(Error::ClusterNotFound {
                 name: start.as_ref().into(),
-            })?;
+            })?
+            .definition
+            .clone();
 
         state
-            .list_dependencies(&cluster.definition)?
+            .list_dependencies(&definition)?
             .into_iter()
             .map(usage)
             .collect::<Result<Vec<_>, _>>()
     }
 
+    /// Run arbitrary program with target cluster as its repository.
+    ///
+    /// Target cluster is opened under an exclusive lock over the cluster store
+    /// at target path, which is released before program runs. Programs like
+    /// interactive shells run for as long as the user wants, and may run
+    /// Oxidot themselves. Once program exits, the cluster store is locked
+    /// exclusively again to sync sparsity rules with the index, see
+    /// [`Cluster::exec_interactive`].
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::Lock`] if cluster store is locked by another
+    ///   process before program runs.
+    /// - Return [`Error::ClusterNotFound`] if cluster does not exist.
+    /// - Return [`Error::Cluster`] if cluster cannot be opened, program
+    ///   fails, or cluster store is locked by another process once program
+    ///   exits.
+    pub fn exec_cluster(
+        path: impl AsRef<Path>,
+        name: impl AsRef<str>,
+        program: impl AsRef<OsStr>,
+        args: impl IntoIterator<Item = impl Into<OsString>>,
+    ) -> Result<()> {
+        let cluster = {
+            let store = Self::open(path.as_ref())?;
+            let mut state = store.lock_state();
+            state.load(name.as_ref())?;
+            state
+                .clusters
+                .remove(name.as_ref())
+                .flatten()
+                .ok_or(Error::ClusterNotFound {
+                    name: name.as_ref().into(),
+                })?
+        };
+
+        cluster.exec_interactive(program, args, || {
+            Ok(StoreLock::acquire(path.as_ref(), LockMode::Exclusive)?)
+        })?;
+
+        Ok(())
+    }
+
+    /// Select clusters by leading arguments that name them.
+    ///
+    /// Leading arguments that name clusters in the cluster store select them
+    /// for [`Selection::Named`], and the remaining arguments are returned
+    /// along with selected cluster names. A "--" that ends leading arguments
+    /// is dropped. Otherwise, every cluster is selected, or every deployed
+    /// cluster, in which case no cluster may be named.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::NamedWithSelection`] if clusters are named without
+    ///   [`Selection::Named`].
+    /// - Return [`Error::Cluster`] if deployed clusters are selected, and any
+    ///   cluster cannot be opened.
+    pub fn select_clusters(
+        &self,
+        selection: Selection,
+        args: &[OsString],
+    ) -> Result<(Vec<String>, Vec<OsString>)> {
+        let names = self.cluster_names();
+        let split = args
+            .iter()
+            .position(|arg| !names.iter().any(|name| arg == name.as_str()))
+            .unwrap_or(args.len());
+        let (named, mut rest) = args.split_at(split);
+        if rest.first().is_some_and(|arg| arg == "--") {
+            rest = &rest[1..];
+        }
+
+        let selected = match selection {
+            Selection::Named => named
+                .iter()
+                .map(|name| name.to_string_lossy().into_owned())
+                .collect(),
+            _ if !named.is_empty() => return Err(Error::NamedWithSelection),
+            Selection::All => names,
+            Selection::Deployed => {
+                let mut state = self.lock_state();
+                let mut deployed = Vec::new();
+                for name in names {
+                    if state.load(&name)?.is_some_and(Cluster::is_deployed) {
+                        deployed.push(name);
+                    }
+                }
+                deployed
+            }
+        };
+
+        Ok((selected, rest.to_vec()))
+    }
+
+    /// Use each target cluster in turn, even if using any of them fails.
+    ///
+    /// Failures are logged as they happen, and summarized once every target
+    /// cluster was used.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::ClustersFailed`] if using any target cluster fails,
+    ///   whether it cannot be found, opened, or the clouser fails.
+    pub fn use_each_cluster<C>(&self, names: &[String], mut usage: C) -> Result<()>
+    where
+        C: FnMut(&str, &Cluster) -> Result<()>,
+    {
+        let mut failed = Vec::new();
+        for name in names {
+            if let Err(error) = self.use_cluster(name, |cluster| usage(name, cluster)) {
+                error!("{name}: {error}");
+                failed.push(name.clone());
+            }
+        }
+
+        if !failed.is_empty() {
+            return Err(Error::ClustersFailed {
+                failed,
+                total: names.len(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Edit cluster definition of target cluster.
+    ///
+    /// Hands cluster definition over to the given clouser as a
+    /// format-preserving editor. The result is committed into the cluster
+    /// with the given message, unless nothing changed. Target cluster is
+    /// opened again afterwards to pick up its new cluster definition. Likely
+    /// mistakes in the new cluster definition are warned about. If the
+    /// cluster was deployed by its default sparsity rules, then deployment is
+    /// reconciled with the new default sparsity rules, see
+    /// [`Cluster::reconcile_default_rules`].
+    ///
+    /// Returns whether cluster definition changed.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::ReadOnly`] if cluster store was opened read-only.
+    /// - Return [`Error::ClusterNotFound`] if cluster does not exist.
+    /// - Return [`Error::Edit`] if cluster definition cannot be edited.
+    /// - Return [`Error::Cluster`] if result cannot be committed, or
+    ///   deployment cannot be reconciled.
+    /// - Fails if clouser also fails for whatever reason.
+    pub fn edit_definition<C>(
+        &self,
+        name: impl AsRef<str>,
+        message: impl AsRef<str>,
+        edit: C,
+    ) -> Result<bool>
+    where
+        C: FnOnce(&mut DefinitionEditor) -> Result<()>,
+    {
+        self.ensure_writable()?;
+        let mut state = self.lock_state();
+        let cluster = state.load(name.as_ref())?.ok_or(Error::ClusterNotFound {
+            name: name.as_ref().into(),
+        })?;
+
+        let source = cluster.definition_source()?;
+        let mut editor: DefinitionEditor = source.parse()?;
+        edit(&mut editor)?;
+        editor.definition()?;
+        let contents = editor.to_string();
+        if contents == source {
+            return Ok(false);
+        }
+        let previous = cluster.definition.settings.clone();
+        cluster.commit_definition(&contents, message)?;
+        for lint in lint(&contents, name.as_ref())? {
+            warn!("cluster {}: {lint}", name.as_ref());
+        }
+
+        // INVARIANT: Reopen cluster so its deployer is configured by new definition.
+        let path = state.store_path.join(format!("{}.git", name.as_ref()));
+        state
+            .clusters
+            .insert(name.as_ref().into(), Some(Git2Cluster::try_open(path)?));
+        if let Some(cluster) = state.load(name.as_ref())? {
+            // INVARIANT: Encryption key is only generated when encryption is first set.
+            let encrypting = |settings: &ClusterSettings| {
+                settings
+                    .encrypt
+                    .as_ref()
+                    .is_some_and(|encrypt| !encrypt.is_empty())
+            };
+            cluster
+                .set_filters(!encrypting(&previous) && encrypting(&cluster.definition.settings))?;
+            cluster.reconcile_default_rules(&previous)?;
+        }
+
+        Ok(true)
+    }
+
     /// Give detailed status information about cluster store.
     ///
     /// Prints the following information:
@@ -322,16 +619,24 @@ pub fn use_cluster_dependencies<C, R>(
     /// - Cluster remote URL.
     /// - Default deployment rules.
     /// - Dependencies of the cluster.
+    /// - Deployment state of named groups, if any.
+    ///
+    /// Warns about deployed paths whose mode drifted from the mode that their
+    /// cluster declares.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::Cluster`] if any cluster cannot be opened.
     #[instrument(skip(self), level = "debug")]
-    pub fn detailed_status(&self) {
-        let state = self.lock_state();
+    pub fn detailed_status(&self) -> Result<()> {
+        let mut state = self.lock_state();
         if state.clusters.is_empty() {
             warn!("cluster store is empty");
-            return;
+            return Ok(());
         }
 
         let mut status = String::new();
-        for (name, entry) in state.clusters.iter() {
+        for (name, entry) in state.load_all()? {
             let deployment = if entry.is_deployed() {
                 "[  deployed]"
             } else {
@@ -349,9 +654,13 @@ pub fn detailed_status(&self) {
                 entry.definition.dependencies,
             );
             status.push_str(data.as_str());
+            status.push_str(group_summary(entry)?.as_str());
+            warn_permission_drift(name, entry);
         }
 
         info!("all avaliable clusters:\n{}", status);
+
+        Ok(())
     }
 
     /// Give status information for deployed clusters only.
@@ -365,16 +674,24 @@ pub fn detailed_status(&self) {
     /// - Cluster remote URL.
     /// - Default deployment rules.
     /// - Dependencies of the cluster.
+    /// - Deployment state of named groups, if any.
+    ///
+    /// Warns about deployed paths whose mode drifted from the mode that their
+    /// cluster declares.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::Cluster`] if any cluster cannot be opened.
     #[instrument(skip(self), level = "debug")]
-    pub fn deployed_only_status(&self) {
-        let state = self.lock_state();
+    pub fn deployed_only_status(&self) -> Result<()> {
+        let mut state = self.lock_state();
         if state.clusters.is_empty() {
             warn!("cluster store is empty");
-            return;
+            return Ok(());
         }
 
         let mut status = String::new();
-        for (name, entry) in state.clusters.iter() {
+        for (name, entry) in state.load_all()? {
             if entry.is_deployed() {
                 let data = format!(
                     "{} -> {} : {}\n  url: {}\n  include: {:#?}\n  dependencies: {:#?}\n",
@@ -386,10 +703,14 @@ pub fn deployed_only_status(&self) {
                     entry.definition.dependencies,
                 );
                 status.push_str(data.as_str());
+                status.push_str(group_summary(entry)?.as_str());
+                warn_permission_drift(name, entry);
             }
         }
 
         info!("all deployed clusters:\n{}", status);
+
+        Ok(())
     }
 
     /// Give status information of undeployed clusters only.
@@ -403,16 +724,21 @@ pub fn deployed_only_status(&self) {
     /// - Cluster remote URL.
     /// - Default deployment rules.
     /// - Dependencies of the cluster.
+    /// - Deployment state of named groups, if any.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::Cluster`] if any cluster cannot be opened.
     #[instrument(skip(self), level = "debug")]
-    pub fn undeployed_only_status(&self) {
-        let state = self.lock_state();
+    pub fn undeployed_only_status(&self) -> Result<()> {
+        let mut state = self.lock_state();
         if state.clusters.is_empty() {
             warn!("cluster store is empty");
-            return;
+            return Ok(());
         }
 
         let mut status = String::new();
-        for (name, entry) in state.clusters.iter() {
+        for (name, entry) in state.load_all()? {
             if !entry.is_deployed() {
                 let data = format!(
                     "{} -> {} : {}\n  url: {}\n  include: {:#?}\n  dependencies: {:#?}\n",
@@ -424,10 +750,13 @@ pub fn undeployed_only_status(&self) {
                     entry.definition.dependencies,
                 );
                 status.push_str(data.as_str());
+                status.push_str(group_summary(entry)?.as_str());
             }
         }
 
         info!("all deployed clusters:\n{}", status);
+
+        Ok(())
     }
 
     /// Show current deployment rules for target cluster.
@@ -452,6 +781,33 @@ pub fn deploy_rules_status(&self, name: impl AsRef<str>) -> Result<()> {
         Ok(())
     }
 
+    /// Show deployment state of named groups for target cluster.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::Cluster`] if sparse checkout configuration
+    ///   file could not be opened to get rule set.
+    #[instrument(skip(self, name), level = "debug")]
+    pub fn groups_status(&self, name: impl AsRef<str>) -> Result<()> {
+        self.use_cluster(name.as_ref(), |cluster| {
+            let groups = cluster
+                .group_status()?
+                .into_iter()
+                .map(|(group, state)| format!("[{state:>10}] {group}"))
+                .collect::<Vec<_>>();
+            if groups.is_empty() {
+                warn!("cluster {} has no groups", name.as_ref());
+                return Ok(());
+            }
+
+            info!("groups for {}:\n  {}", name.as_ref(), groups.join("\n  "));
+
+            Ok(())
+        })?;
+
+        Ok(())
+    }
+
     /// Give listing of currently tracked files for target cluster.
     ///
     /// # Errors
@@ -474,28 +830,131 @@ pub fn tracked_files_status(&self, name: impl AsRef<str>) -> Result<()> {
         Ok(())
     }
 
+    /// Names of all clusters in cluster store.
+    ///
+    /// Does not open any cluster. Names are sorted alphabetically.
+    pub fn cluster_names(&self) -> Vec<String> {
+        let state = self.lock_state();
+        let mut names = state.clusters.keys().cloned().collect::<Vec<_>>();
+        names.sort();
+
+        names
+    }
+
+    fn ensure_writable(&self) -> Result<()> {
+        if self.lock.mode() != LockMode::Exclusive {
+            return Err(Error::ReadOnly);
+        }
+
+        Ok(())
+    }
+
     #[inline]
     fn lock_state(&self) -> MutexGuard<'_, StoreState> {
         self.state.lock().unwrap()
     }
 }
 
+/// How clusters are selected, see [`Store::select_clusters`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Selection {
+    /// Clusters named by leading arguments.
+    Named,
+
+    /// Every cluster in cluster store.
+    All,
+
+    /// Every deployed cluster in cluster store.
+    Deployed,
+}
+
 #[derive(Debug)]
 pub(crate) struct StoreState {
     pub(crate) store_path: PathBuf,
-    pub(crate) clusters: HashMap<String, Cluster>,
+
+    /// Clusters by name, where [`None`] marks a cluster not yet opened.
+    pub(crate) clusters: HashMap<String, Option<Cluster>>,
+
+    /// Whether deployment hooks of clusters are run.
+    pub(crate) hooks: bool,
+
+    /// Rewrite rules for URLs that clusters are cloned from.
+    pub(crate) url_rewrites: UrlRewrites,
+
+    /// Whether cluster store is locked exclusively, such that clusters can be
+    /// written to when they are opened.
+    pub(crate) writable: bool,
 }
 
 impl StoreState {
-    pub(crate) fn new(store_path: impl Into<PathBuf>, clusters: HashMap<String, Cluster>) -> Self {
+    pub(crate) fn new(
+        store_path: impl Into<PathBuf>,
+        clusters: HashMap<String, Option<Cluster>>,
+    ) -> Self {
         Self {
             store_path: store_path.into(),
             clusters,
+            hooks: true,
+            url_rewrites: UrlRewrites::default(),
+            writable: false,
         }
     }
 
-    pub(crate) fn find_unresolved_dependencies(
+    /// Apply rewrite rules to URLs that a cluster is about to be cloned from.
+    pub(crate) fn rewrite_urls(
         &self,
+        urls: impl IntoIterator<Item = impl AsRef<str>>,
+    ) -> Vec<String> {
+        urls.into_iter()
+            .map(|url| {
+                let rewritten = self.url_rewrites.apply(url.as_ref());
+                if rewritten != url.as_ref() {
+                    info!("rewrite {:?} to {rewritten:?}", url.as_ref());
+                }
+                rewritten
+            })
+            .collect()
+    }
+
+    /// Get cluster by name, opening it if this is the first access.
+    ///
+    /// Returns [`None`] if cluster store does not contain target cluster.
+    pub(crate) fn load(&mut self, name: &str) -> Result<Option<&Cluster>> {
+        let Some(entry) = self.clusters.get_mut(name) else {
+            return Ok(None);
+        };
+
+        if entry.is_none() {
+            *entry = Some(open_cluster(&self.store_path, name, self.writable)?);
+        }
+
+        if let Some(cluster) = entry.as_ref() {
+            cluster.set_hooks_enabled(self.hooks);
+        }
+
+        Ok(entry.as_ref())
+    }
+
+    /// Open every cluster not yet opened, and list all clusters by name.
+    pub(crate) fn load_all(&mut self) -> Result<Vec<(&String, &Cluster)>> {
+        for (name, entry) in self.clusters.iter_mut() {
+            if entry.is_none() {
+                *entry = Some(open_cluster(&self.store_path, name, self.writable)?);
+            }
+        }
+
+        let mut clusters = self
+            .clusters
+            .iter()
+            .filter_map(|(name, entry)| entry.as_ref().map(|cluster| (name, cluster)))
+            .collect::<Vec<_>>();
+        clusters.sort_by_key(|(name, _)| *name);
+
+        Ok(clusters)
+    }
+
+    pub(crate) fn find_unresolved_dependencies(
+        &mut self,
         parent: &ClusterDefinition,
     ) -> Result<Vec<ClusterDependency>> {
         let mut unresolved = Vec::new();
@@ -519,7 +978,7 @@ pub(crate) fn find_unresolved_dependencies(
                 unresolved.push(current.clone());
             }
 
-            if let Some(cluster) = self.clusters.get(&current.name) {
+            if let Some(cluster) = self.load(&current.name)? {
                 if let Some(deps) = &cluster.definition.dependencies {
                     for dep in deps {
                         stack.push_back(dep.clone());
@@ -531,7 +990,7 @@ pub(crate) fn find_unresolved_dependencies(
         Ok(unresolved)
     }
 
-    pub(crate) fn list_dependencies(&self, start: &ClusterDefinition) -> Result<Vec<&Cluster>> {
+    pub(crate) fn list_dependencies(&mut self, start: &ClusterDefinition) -> Result<Vec<&Cluster>> {
         let mut listing = Vec::new();
         let mut visited = HashSet::new();
         let mut stack = VecDeque::new();
@@ -549,17 +1008,57 @@ pub(crate) fn list_dependencies(&self, start: &ClusterDefinition) -> Result<Vec<
                 continue;
             }
 
-            if let Some(cluster) = self.clusters.get(&current.name) {
-                listing.push(cluster);
+            if let Some(cluster) = self.load(&current.name)? {
                 if let Some(deps) = &cluster.definition.dependencies {
                     for dep in deps {
                         stack.push_back(dep.clone());
                     }
                 }
+                listing.push(current.name);
             }
         }
 
-        Ok(listing)
+        // INVARIANT: Every listed cluster was opened during traversal.
+        Ok(listing
+            .iter()
+            .filter_map(|name| self.clusters.get(name).and_then(Option::as_ref))
+            .collect())
+    }
+}
+
+/// Open cluster of cluster store by name.
+///
+/// Filters of cluster are only brought in line with its definition if the
+/// cluster store is writable, because that writes to the cluster.
+fn open_cluster(store_path: &Path, name: &str, writable: bool) -> Result<Cluster> {
+    let cluster = Git2Cluster::try_open(store_path.join(format!("{name}.git")))?;
+    if writable {
+        cluster.set_filters(false)?;
+    }
+
+    Ok(cluster)
+}
+
+fn group_summary(cluster: &Cluster) -> Result<String> {
+    let groups = cluster
+        .group_status()?
+        .into_iter()
+        .map(|(group, state)| format!("{group} ({state})"))
+        .collect::<Vec<_>>();
+    if groups.is_empty() {
+        return Ok(String::new());
+    }
+
+    Ok(format!("  groups: {}\n", groups.join(", ")))
+}
+
+fn warn_permission_drift(name: &str, cluster: &Cluster) {
+    // INVARIANT: Status is still given if permissions cannot be checked.
+    match cluster.permission_drift() {
+        Ok(drift) => drift
+            .iter()
+            .for_each(|drift| warn!("cluster {name}: {drift}")),
+        Err(error) => debug!("cannot check permissions of {name:?}: {error}"),
     }
 }
 
@@ -570,6 +1069,27 @@ pub enum Error {
     #[error("cluster {name:?} not found in cluster store")]
     ClusterNotFound { name: String },
 
+    /// Clusters are named while every cluster, or every deployed cluster is
+    /// selected.
+    #[error("clusters cannot be named when every cluster is selected")]
+    NamedWithSelection,
+
+    /// Using some of the target clusters failed.
+    #[error(
+        "failed for {} of {total} clusters: {}",
+        failed.len(),
+        failed.join(", ")
+    )]
+    ClustersFailed { failed: Vec<String>, total: usize },
+
+    /// Cluster store was opened for reading only.
+    #[error("cluster store was opened read-only")]
+    ReadOnly,
+
+    /// Cluster store cannot be locked for this process.
+    #[error(transparent)]
+    Lock(#[from] crate::store::lock::Error),
+
     /// Failed to use glob patterns for directory processing.
     #[error(transparent)]
     Glob(#[from] glob::GlobError),
@@ -582,6 +1102,14 @@ pub enum Error {
     #[error(transparent)]
     Cluster(#[from] crate::cluster::Error),
 
+    /// Cluster definition cannot be edited.
+    #[error(transparent)]
+    Edit(#[from] crate::config::edit::Error),
+
+    /// Cluster definition cannot be linted.
+    #[error(transparent)]
+    Lint(#[from] crate::config::lint::Error),
+
     /// Threads failed to properly join to main thread.
     #[error(transparent)]
     Join(#[from] tokio::task::JoinError),
@@ -593,3 +1121,161 @@ pub enum Error {
 
 /// Friendly result alias :3
 pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cluster::{deploy::Error as DeployError, Error as ClusterError};
+    use git2::Repository;
+    use pretty_assertions::assert_eq;
+    use sealed_test::prelude::*;
+    use std::fs::{create_dir_all, read_to_string, write};
+
+    fn init_cluster(store: &Store, name: &str) -> anyhow::Result<()> {
+        // INVARIANT: Commits need an identity that does not depend on the host.
+        let mut config = Repository::init_bare(format!("store/{name}.git"))?.config()?;
+        config.set_str("user.name", "blah")?;
+        config.set_str("user.email", "blah@blah.org")?;
+
+        create_dir_all("home")?;
+        let home = std::env::current_dir()?.join("home");
+        let definition = format!(
+            "[settings]\ndescription = \"{name}\"\nwork_tree_alias = {:?}\n\n\
+             [settings.remote]\nurl = \"https://blah.org/{name}.git\"\n",
+            home.display()
+        );
+        store.init_cluster(name, definition.parse()?)?;
+
+        Ok(())
+    }
+
+    #[sealed_test]
+    fn broken_cluster_does_not_block_others() -> anyhow::Result<()> {
+        init_cluster(&Store::open("store")?, "foo")?;
+        create_dir_all("store/broken.git")?;
+
+        let store = Store::open_shared("store")?;
+        assert_eq!(store.cluster_names(), vec!["broken", "foo"]);
+        let result = store.use_cluster("foo", |cluster| {
+            Ok(cluster.definition.settings.description.clone())
+        })?;
+        assert_eq!(result, "foo");
+        assert!(matches!(
+            store.use_cluster("broken", |_| Ok(())),
+            Err(Error::Cluster(_))
+        ));
+        assert!(matches!(
+            store.use_cluster("missing", |_| Ok(())),
+            Err(Error::ClusterNotFound { name }) if name == "missing"
+        ));
+
+        Ok(())
+    }
+
+    #[sealed_test]
+    fn loaded_cluster_is_kept() -> anyhow::Result<()> {
+        init_cluster(&Store::open("store")?, "foo")?;
+
+        let store = Store::open("store")?;
+        let description = |cluster: &Cluster| Ok(cluster.definition.settings.description.clone());
+        assert_eq!(store.use_cluster("foo", description)?, "foo");
+
+        // INVARIANT: Cluster would fail to open again, so it must be the one loaded before.
+        remove_dir_all("store/foo.git")?;
+        assert_eq!(store.use_cluster("foo", description)?, "foo");
+
+        Ok(())
+    }
+
+    #[sealed_test]
+    fn select_clusters_by_selection() -> anyhow::Result<()> {
+        let store = Store::open("store")?;
+        for name in ["foo", "bar", "baz"] {
+            init_cluster(&store, name)?;
+        }
+        drop(store);
+        write("home/.vimrc", "set nu\n")?;
+        let script =
+            r#"git add --sparse "$GIT_WORK_TREE/.vimrc" && git commit -qm "chore: add vimrc""#;
+        Store::exec_cluster("store", "foo", "sh", ["-c", script])?;
+
+        let store = Store::open("store")?;
+        let args = |args: &[&str]| args.iter().map(OsString::from).collect::<Vec<_>>();
+        let names = |names: &[&str]| names.iter().map(|name| name.to_string()).collect();
+
+        let result = store.select_clusters(Selection::Named, &args(&["foo", "bar", "--", "log"]))?;
+        assert_eq!(result, (names(&["foo", "bar"]), args(&["log"])));
+
+        // Named clusters end at first argument that names no cluster.
+        let result = store.select_clusters(Selection::Named, &args(&["log", "foo"]))?;
+        assert_eq!(result, (names(&[]), args(&["log", "foo"])));
+
+        let result = store.select_clusters(Selection::All, &args(&["log"]))?;
+        assert_eq!(result, (names(&["bar", "baz", "foo"]), args(&["log"])));
+
+        let result = store.select_clusters(Selection::Deployed, &args(&["--", "log"]))?;
+        assert_eq!(result, (names(&["foo"]), args(&["log"])));
+
+        assert!(matches!(
+            store.select_clusters(Selection::All, &args(&["foo", "log"])),
+            Err(Error::NamedWithSelection)
+        ));
+
+        Ok(())
+    }
+
+    #[sealed_test]
+    fn use_each_cluster_continues_after_failure() -> anyhow::Result<()> {
+        let store = Store::open("store")?;
+        for name in ["foo", "bar", "baz"] {
+            init_cluster(&store, name)?;
+        }
+
+        let targets = ["bar", "missing", "baz", "foo"].map(String::from);
+        let mut used = Vec::new();
+        let result = store.use_each_cluster(&targets, |name, _| {
+            used.push(name.to_string());
+            match name {
+                "baz" => Err(Error::ReadOnly),
+                _ => Ok(()),
+            }
+        });
+        assert_eq!(used, vec!["bar", "baz", "foo"]);
+        assert!(matches!(
+            result,
+            Err(Error::ClustersFailed { failed, total: 4 }) if failed == ["missing", "baz"]
+        ));
+
+        Ok(())
+    }
+
+    #[sealed_test]
+    fn exec_cluster_syncs_under_store_lock() -> anyhow::Result<()> {
+        init_cluster(&Store::open("store")?, "foo")?;
+        write("home/.vimrc", "set nu\n")?;
+        write("home/.bashrc", "set -o vi\n")?;
+        let add = |file: &str| format!(r#"git add --sparse "$GIT_WORK_TREE/{file}""#);
+
+        Store::exec_cluster("store", "foo", "sh", ["-c", &add(".vimrc")])?;
+        let rules = Store::open_shared("store")?
+            .use_cluster("foo", |cluster| Ok(cluster.list_deploy_rules()?))?;
+        assert_eq!(rules, vec![".vimrc"]);
+
+        // INVARIANT: Another process keeps holding the store lock after program exits.
+        let script = format!(
+            "{} && (flock -s store/oxidot.lock sleep 3 &) && sleep 1",
+            add(".bashrc")
+        );
+        let result = Store::exec_cluster("store", "foo", "sh", ["-c", &script]);
+        assert!(matches!(
+            result,
+            Err(Error::Cluster(ClusterError::Deployment(DeployError::Lock(
+                lock::Error::Locked { .. }
+            ))))
+        ));
+        let rules = read_to_string("store/foo.git/info/sparse-checkout")?;
+        assert_eq!(rules, ".vimrc\n");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,237 @@
+// SPDX-FileCopyrightText: 2025 Jason Pena <jasonpena@awkless.com>
+// SPDX-License-Identifier: MIT
+
+//! Cluster store process locking.
+//!
+//! The cluster store can be accessed by multiple Oxidot processes at the same
+//! time, e.g., cloning a cluster in one terminal while deploying another in a
+//! second terminal. Both processes would end up writing to the same sparse
+//! checkout files and index locks at the same time. Thus, the cluster store is
+//! guarded by an advisory lock file at the top-level of the cluster store.
+//!
+//! # Lock Modes
+//!
+//! Processes that modify the cluster store take an _exclusive_ lock, such that
+//! no other process can access the cluster store until it is done. Processes
+//! that only read from the cluster store take a _shared_ lock, such that they
+//! can run alongside each other, but never alongside a process holding an
+//! exclusive lock.
+//!
+//! # Stale Locks
+//!
+//! The lock itself is held through the operating system's file locking
+//! facilities. It is released by the operating system whenever the process
+//! holding it exits, even when that process crashes. However, the lock file
+//! also records the PID of the process holding an exclusive lock for error
+//! reporting, which is cleared again on release. A PID left behind by a
+//! crashed process is considered stale if the process it refers to is no
+//! longer running, and is never reported back to the user.
+
+use fs4::{FileExt, TryLockError};
+use std::{
+    fs::{File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+use tracing::{debug, instrument};
+
+/// Name of lock file at top-level of cluster store.
+pub const LOCK_FILE: &str = "oxidot.lock";
+
+/// How a cluster store is locked.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LockMode {
+    /// Other processes may read, but not modify the cluster store.
+    Shared,
+
+    /// No other process may access the cluster store.
+    #[default]
+    Exclusive,
+}
+
+/// Advisory lock over an entire cluster store.
+///
+/// Lock is released when dropped.
+#[derive(Debug)]
+pub struct StoreLock {
+    file: File,
+    mode: LockMode,
+}
+
+impl StoreLock {
+    /// Acquire lock over cluster store at target path.
+    ///
+    /// Does not wait for other processes to release their lock.
+    ///
+    /// # Errors
+    ///
+    /// - Return [`Error::Locked`] if another process holds a conflicting
+    ///   lock.
+    /// - Return [`Error::Io`] if lock file cannot be opened, or written to.
+    #[instrument(skip(store_path), level = "debug")]
+    pub fn acquire(store_path: impl AsRef<Path>, mode: LockMode) -> Result<Self> {
+        let lock_path = store_path.as_ref().join(LOCK_FILE);
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .write(true)
+            .truncate(false)
+            .open(&lock_path)
+            .map_err(|err| Error::Io {
+                source: err,
+                lock_path: lock_path.clone(),
+            })?;
+
+        // INVARIANT: Use fs4 directly, std's inherent file locking methods would shadow it.
+        let locked = match mode {
+            LockMode::Shared => FileExt::try_lock_shared(&file),
+            LockMode::Exclusive => FileExt::try_lock(&file),
+        };
+
+        let holder = recorded_pid(&mut file);
+        match locked {
+            Ok(()) => (),
+            Err(TryLockError::WouldBlock) => {
+                return Err(Error::Locked {
+                    pid: holder.filter(|pid| is_running(*pid)),
+                })
+            }
+            Err(TryLockError::Error(err)) => {
+                return Err(Error::Io {
+                    source: err,
+                    lock_path,
+                })
+            }
+        }
+
+        if mode == LockMode::Exclusive {
+            if let Some(pid) = holder.filter(|pid| !is_running(*pid)) {
+                debug!("clear stale lock left behind by PID {pid}");
+            }
+
+            let pid = std::process::id();
+            file.set_len(0)
+                .and_then(|_| file.seek(SeekFrom::Start(0)))
+                .and_then(|_| file.write_all(pid.to_string().as_bytes()))
+                .map_err(|err| Error::Io {
+                    source: err,
+                    lock_path,
+                })?;
+        }
+
+        Ok(Self { file, mode })
+    }
+
+    /// Mode that cluster store was locked with.
+    pub fn mode(&self) -> LockMode {
+        self.mode
+    }
+}
+
+impl Drop for StoreLock {
+    fn drop(&mut self) {
+        // INVARIANT: Only clear PID while still holding the exclusive lock.
+        if self.mode == LockMode::Exclusive {
+            let _ = self.file.set_len(0);
+        }
+        let _ = FileExt::unlock(&self.file);
+    }
+}
+
+fn recorded_pid(file: &mut File) -> Option<u32> {
+    let mut content = String::new();
+    file.read_to_string(&mut content).ok()?;
+    content.trim().parse().ok()
+}
+
+#[cfg(target_os = "linux")]
+fn is_running(pid: u32) -> bool {
+    Path::new("/proc").join(pid.to_string()).exists()
+}
+
+// INVARIANT: Without a way to tell, assume recorded process is still running.
+#[cfg(not(target_os = "linux"))]
+fn is_running(_pid: u32) -> bool {
+    true
+}
+
+/// Cluster store locking error types.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Another process holds a conflicting lock over the cluster store.
+    #[error("{}", match pid {
+        Some(pid) => format!("cluster store is locked by PID {pid}"),
+        None => "cluster store is locked by another oxidot process".into(),
+    })]
+    Locked { pid: Option<u32> },
+
+    /// Lock file cannot be accessed.
+    #[error("failed to access lock file at {:?}", lock_path.display())]
+    Io {
+        #[source]
+        source: std::io::Error,
+        lock_path: PathBuf,
+    },
+}
+
+/// Friendly result alias :3
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use sealed_test::prelude::*;
+    use std::fs::{read_to_string, write};
+
+    #[sealed_test]
+    fn shared_and_exclusive_locks_conflict() -> Result<()> {
+        let first = StoreLock::acquire(".", LockMode::Shared)?;
+        let second = StoreLock::acquire(".", LockMode::Shared)?;
+        assert_eq!(first.mode(), LockMode::Shared);
+        assert!(matches!(
+            StoreLock::acquire(".", LockMode::Exclusive),
+            Err(Error::Locked { pid: None })
+        ));
+        drop((first, second));
+
+        let exclusive = StoreLock::acquire(".", LockMode::Exclusive)?;
+        assert_eq!(
+            read_to_string(LOCK_FILE).unwrap(),
+            std::process::id().to_string()
+        );
+        #[cfg(target_os = "linux")]
+        assert!(matches!(
+            StoreLock::acquire(".", LockMode::Shared),
+            Err(Error::Locked { pid: Some(pid) }) if pid == std::process::id()
+        ));
+        drop(exclusive);
+
+        assert_eq!(read_to_string(LOCK_FILE).unwrap(), "");
+        StoreLock::acquire(".", LockMode::Shared)?;
+
+        Ok(())
+    }
+
+    #[sealed_test]
+    fn stale_pid_is_replaced() -> Result<()> {
+        // INVARIANT: PIDs never go beyond 2^22 on Linux.
+        write(LOCK_FILE, "4294967295").unwrap();
+
+        let shared = StoreLock::acquire(".", LockMode::Shared)?;
+        #[cfg(target_os = "linux")]
+        assert!(matches!(
+            StoreLock::acquire(".", LockMode::Exclusive),
+            Err(Error::Locked { pid: None })
+        ));
+        drop(shared);
+
+        let _exclusive = StoreLock::acquire(".", LockMode::Exclusive)?;
+        assert_eq!(
+            read_to_string(LOCK_FILE).unwrap(),
+            std::process::id().to_string()
+        );
+
+        Ok(())
+    }
+}